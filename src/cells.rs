@@ -1,29 +1,106 @@
 //! This file defines the different types of cells (particles) in our falling sand simulation
 //! and their properties like density, temperature behavior, and special characteristics.
 
-// Import the random number generator function from our main library
-use crate::rand;
+// Import the random number generator trait
+use crate::random::Random;
+// Import the registry that the properties of each cell type are read into
+use crate::materials::MaterialRegistry;
 
 /// This struct defines all the properties that each type of cell can have.
+/// The properties of every cell type are read in from materials.txt (see materials.rs)
 /// The #[derive] attribute automatically implements several traits for our struct:
-/// - Copy: Allows the struct to be copied by value instead of moved
 /// - Clone: Provides a method to explicitly create a copy
 /// - Debug: Allows the struct to be printed with {:?} format
 /// - PartialEq: Allows comparison with == and !=
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct CellTypeProperties {
-    /// The display name of this cell type (static lifetime means it lives for the entire program)
-    pub name: &'static str,
+    /// The display name of this cell type
+    pub name: String,
     /// The enum variant representing this cell type
     pub cell_type: CellType,
+    /// The colour this cell is drawn in, as red, green and blue (from 0 to 255)
+    pub colour: (u8, u8, u8),
     /// How dense this cell is (affects falling behavior)
     pub density: f64,
+    /// How this cell moves around (eg. falling like sand or flowing like water)
+    pub movement: Movement,
     /// How quickly this cell heats up or cools down
     pub temp_coefficient: f32,
+    /// The lowest and highest temperatures that a new cell of this type starts out at
+    pub initial_temp: (f32, f32),
+    /// The shortest and longest number of ticks that a cell of this type lasts before it dissipates
+    /// into empty space, or (0, 0) if it lasts forever (eg. smoke fades away, but sand doesn't)
+    pub lifetime: (u16, u16),
     /// Whether this cell can catch fire
     pub flammable: bool,
+    /// The temperature at which a flammable cell catches fire (ignored if it's not flammable)
+    pub ignition_temp: f32,
+    /// The chance that a flammable cell gives off smoke when it catches fire
+    pub smoke: f64,
+    /// Whether this cell explodes instead of burning when it ignites
+    pub explosive: bool,
     /// Whether this cell can be dissolved by acid
     pub dissolvable: bool,
+    /// Whether this cell conducts electricity (so sparks travel along it, like a wire)
+    pub conductive: bool,
+    /// The other cell types this cell turns into when its temperature crosses a threshold
+    pub phase_changes: Vec<PhaseChange>,
+    /// The other cell types this cell reacts with when they're touching
+    pub reactions: Vec<Reaction>,
+}
+
+/// This enum describes the different ways that cells can move around
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Movement {
+    /// Stays where it is (eg. rock)
+    Static,
+    /// Falls straight down, or diagonally to pile up (eg. sand)
+    Granular,
+    /// Falls, and also flows sideways to fill containers (eg. water)
+    Liquid,
+    /// Drifts around randomly, so lighter gases end up above heavier ones (eg. propane)
+    Gas,
+}
+
+/// This enum describes a change of state (eg. melting, freezing, boiling, or condensing)
+/// which happens when a cell's temperature crosses a threshold
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PhaseChange {
+    /// Change into the given cell type when hotter than the given temperature
+    Above(f32, CellType),
+    /// Change into the given cell type when colder than the given temperature
+    Below(f32, CellType),
+}
+
+/// This struct describes a reaction between two touching cells (eg. water cooling lava into rock)
+/// which has a chance of happening every tick that they're touching
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reaction {
+    /// The cells that this cell reacts with
+    pub other: Reactant,
+    /// The chance of reacting in each tick
+    pub probability: f64,
+    /// The lowest and highest temperatures of this cell at which the reaction can happen
+    pub temp_range: (f32, f32),
+    /// The cell type that this cell turns into (or None if it stays the same)
+    pub becomes: Option<CellType>,
+    /// The cell type that the other cell turns into (or None if it stays the same)
+    pub other_becomes: Option<CellType>,
+    /// The heat given off by the reaction, which is added to both cells (or taken away, if it's negative)
+    pub heat: f32,
+}
+
+/// This enum describes which cells a reaction can happen with
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Reactant {
+    /// Only cells of the given type
+    Type(CellType),
+    /// Any cell that can catch fire
+    Flammable,
+    /// Any cell that explodes when it catches fire
+    Explosive,
+    /// Any cell that can be dissolved by acid
+    Dissolvable,
 }
 
 /// This enum defines all the different types of cells in our simulation.
@@ -53,11 +130,40 @@ pub enum CellType {
     Lava,
     /// Dissolves materials it touches
     Acid,
+    /// Hot gas that condenses back into water
+    Steam,
+    /// Frozen water that melts when warmed
+    Ice,
+    /// Acid mixed with water, which dissolves materials more slowly
+    DilutedAcid,
+    /// Gas given off by burning materials, which fades away over time
+    Smoke,
+    /// Frozen flakes of water that fall and pile up, and pack down into ice
+    Snow,
+    /// Granular material that dissolves in water
+    Salt,
+    /// Water with salt dissolved in it, which freezes at a lower temperature than water
+    Saltwater,
+    /// Falls like sand, and sprouts into a plant when it's resting on sand and touching water
+    Seed,
+    /// Grows upwards (and branches out) by drinking the water around it
+    Plant,
+    /// Solid metal that conducts electricity, for making wires
+    Metal,
+    /// Powers the metal it's touching, by sending sparks along it
+    Battery,
+    /// Keeps filling the empty space around it with a copy of the first material that touches it
+    Clone,
+    /// Deletes any moving material (eg. sand, water or gas) that touches it
+    Drain,
+    /// Slowly drips out the first material that touches it, like a tap
+    Tap,
 }
 
-/// This static array contains all the cell types (except Empty) for easy iteration
+/// This static array contains all the cell types, in the same order as the enum definition above
 /// Static means this array exists for the entire program and has a fixed size
-static CELL_TYPES: [CellType; 10] = [
+pub(crate) static CELL_TYPES: [CellType; 25] = [
+    CellType::Empty,
     CellType::Rock,
     CellType::Wood,
     CellType::Sand,
@@ -68,121 +174,30 @@ static CELL_TYPES: [CellType; 10] = [
     CellType::Fire,
     CellType::Lava,
     CellType::Acid,
-];
-
-/// This static array defines the specific properties for each cell type
-/// The array must have an entry for each CellType variant, in the same order
-/// as the enum definition above
-static CELL_PROPERTIES: [CellTypeProperties; 11] = [
-    // Empty cells have no density and minimal temperature effects
-    CellTypeProperties {
-        name: "Empty",
-        cell_type: CellType::Empty,
-        density: 0.0,                // No density (lightest)
-        temp_coefficient: 0.1,       // Changes temperature very slowly
-        flammable: false,            // Can't catch fire
-        dissolvable: false,          // Can't be dissolved
-    },
-    // Rock is solid and stable
-    CellTypeProperties {
-        name: "Rock",
-        cell_type: CellType::Rock,
-        density: 3.0,                // High density (heavy)
-        temp_coefficient: 0.1,       // Changes temperature very slowly
-        flammable: false,            // Can't catch fire
-        dissolvable: true,           // Can be dissolved by acid
-    },
-    // Wood is solid but can burn
-    CellTypeProperties {
-        name: "Wood",
-        cell_type: CellType::Wood,
-        density: 3.0,                // High density (heavy)
-        temp_coefficient: 0.8,       // Changes temperature moderately
-        flammable: true,             // Can catch fire
-        dissolvable: true,           // Can be dissolved by acid
-    },
-    // Sand behaves like a flowing solid
-    CellTypeProperties {
-        name: "Sand",
-        cell_type: CellType::Sand,
-        density: 3.0,                // High density (heavy)
-        temp_coefficient: 0.1,       // Changes temperature very slowly
-        flammable: false,            // Can't catch fire
-        dissolvable: true,           // Can be dissolved by acid
-    },
-    // Gunpowder is explosive when heated
-    CellTypeProperties {
-        name: "Gunpowder",
-        cell_type: CellType::Gunpowder,
-        density: 3.0,                // High density (heavy)
-        temp_coefficient: 20.0,      // Heats up very quickly
-        flammable: true,             // Can catch fire (and explode)
-        dissolvable: false,          // Can't be dissolved by acid
-    },
-    // Water is a flowing liquid
-    CellTypeProperties {
-        name: "Water",
-        cell_type: CellType::Water,
-        density: 1.0,                // Medium density
-        temp_coefficient: 0.4,       // Changes temperature slowly
-        flammable: false,            // Can't catch fire
-        dissolvable: false,          // Can't be dissolved by acid
-    },
-    // Oil is flammable and floats on water
-    CellTypeProperties {
-        name: "Oil",
-        cell_type: CellType::Oil,
-        density: 0.8,                // Less dense than water (floats on water)
-        temp_coefficient: 10.0,      // Heats up quickly
-        flammable: true,             // Can catch fire
-        dissolvable: false,          // Can't be dissolved by acid
-    },
-    // Propane is a rising gas that's highly flammable
-    CellTypeProperties {
-        name: "Propane",
-        cell_type: CellType::Propane,
-        density: 0.1,                // Very low density (rises)
-        temp_coefficient: 200.0,     // Heats up extremely quickly
-        flammable: true,             // Can catch fire
-        dissolvable: false,          // Can't be dissolved by acid
-    },
-    // Fire spreads to flammable materials and rises
-    CellTypeProperties {
-        name: "Fire",
-        cell_type: CellType::Fire,
-        density: 0.01,               // Extremely low density (rises quickly)
-        temp_coefficient: 1.0,       // Normal temperature behavior
-        flammable: false,            // Can't catch fire (it is fire)
-        dissolvable: false,          // Can't be dissolved by acid
-    },
-    // Lava is hot and heavy
-    CellTypeProperties {
-        name: "Lava",
-        cell_type: CellType::Lava,
-        density: 3.0,                // High density (heavy)
-        temp_coefficient: 100.0,     // Maintains high temperature
-        flammable: false,            // Can't catch fire
-        dissolvable: true,           // Can be dissolved by acid
-    },
-    // Acid dissolves other materials
-    CellTypeProperties {
-        name: "Acid",
-        cell_type: CellType::Acid,
-        density: 1.2,                // Slightly more dense than water (sinks in water)
-        temp_coefficient: 0.1,       // Changes temperature very slowly
-        flammable: false,            // Can't catch fire
-        dissolvable: false,          // Can't be dissolved by acid (itself)
-    },
+    CellType::Steam,
+    CellType::Ice,
+    CellType::DilutedAcid,
+    CellType::Smoke,
+    CellType::Snow,
+    CellType::Salt,
+    CellType::Saltwater,
+    CellType::Seed,
+    CellType::Plant,
+    CellType::Metal,
+    CellType::Battery,
+    CellType::Clone,
+    CellType::Drain,
+    CellType::Tap,
 ];
 
 /// This implementation block adds methods to the CellType enum
 impl CellType {
     /// Creates a random cell type, with a bias toward Empty cells
     /// This is used for debugging or for special effects in the simulation
-    pub fn random() -> CellType {
-        // Generate a random number between 0 and 19 using our rand() function
+    pub fn random(rng: &mut dyn Random) -> CellType {
+        // Generate a random number between 0 and 19 using the given random number generator
         // Then use pattern matching to determine which cell type to return
-        match (rand() * 20.0) as u32 {
+        match (rng.rand() * 20.0) as u32 {
             0 => CellType::Rock,
             1 => CellType::Sand,
             2 => CellType::Water,
@@ -190,20 +205,64 @@ impl CellType {
         }
     }
 
-    /// Returns an iterator over all cell types (except Empty)
-    /// The 'a lifetime parameter indicates how long the returned iterator is valid
-    pub fn iter<'a>() -> std::slice::Iter<'a, CellType> {
-        CELL_TYPES.iter()    // Return an iterator to the CELL_TYPES array
+    /// Returns an iterator over all cell types (except Empty), in the order they're defined in materials.txt
+    pub fn iter() -> std::slice::Iter<'static, CellType> {
+        MaterialRegistry::get().cell_types().iter()
     }
 
-    /// Gets the properties for a given cell type
-    /// The 'a lifetime parameter indicates how long the returned reference is valid
-    pub fn get_properties<'a>(cell_type: CellType) -> &'a CellTypeProperties {
-        // Look up the properties in the CELL_PROPERTIES array using the enum value as an index
-        &CELL_PROPERTIES[cell_type as usize]
+    /// Gets the properties for a given cell type, from the registry of materials
+    pub fn get_properties(cell_type: CellType) -> &'static CellTypeProperties {
+        MaterialRegistry::get().get_properties(cell_type)
+    }
+
+    /// Checks if cells of this type emit copies of another material (eg. a tap), which is stored in Cell::emits
+    pub fn is_emitter(self) -> bool {
+        matches!(self, CellType::Clone | CellType::Tap)
+    }
+
+    /// Checks if this is a material that an emitter can make copies of
+    /// Emitters can't copy empty space, or other emitters and drains (which would make them fill up the space with themselves)
+    pub fn can_be_emitted(self) -> bool {
+        !matches!(self, CellType::Empty | CellType::Drain) && !self.is_emitter()
+    }
+
+    /// Finds the cell type with the given name (ignoring upper and lower case), including Empty
+    pub fn from_name(name: &str) -> Option<CellType> {
+        CELL_TYPES.iter()
+            .copied()
+            .find(|cell_type| CellType::get_properties(*cell_type).name.eq_ignore_ascii_case(name))
     }
 }
 
+impl Reactant {
+    /// Checks if a cell of the given type can take part in a reaction with this reactant
+    pub fn matches(&self, cell_type: CellType) -> bool {
+        match self {
+            Reactant::Type(reactant_type) => *reactant_type == cell_type,
+            Reactant::Flammable => CellType::get_properties(cell_type).flammable,
+            Reactant::Explosive => CellType::get_properties(cell_type).explosive,
+            Reactant::Dissolvable => CellType::get_properties(cell_type).dissolvable,
+        }
+    }
+}
+
+impl CellTypeProperties {
+    /// Returns the cell type that a cell of this type turns into at the given temperature, if any
+    pub fn phase_change_at(&self, temp: f32) -> Option<CellType> {
+        self.phase_changes.iter().find_map(|change| match *change {
+            PhaseChange::Above(threshold, cell_type) if temp > threshold => Some(cell_type),
+            PhaseChange::Below(threshold, cell_type) if temp < threshold => Some(cell_type),
+            _ => None,
+        })
+    }
+}
+
+/// The number of ticks that a conductive cell carries a spark for, after which the spark has moved on
+pub const SPARK_DURATION: u8 = 2;
+/// The number of ticks after a spark has passed before a conductive cell can carry another one
+/// Without this, a spark would travel backwards along the wire as well as forwards, and never die out
+pub const SPARK_REFRACTORY_PERIOD: u8 = 4;
+
 /// This struct represents an actual cell in the simulation grid
 /// Each position in our grid contains one of these cells
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -214,6 +273,17 @@ pub struct Cell {
     pub generation: u8,
     /// The temperature of this cell, affects behavior
     pub temp: f32,
+    /// The horizontal velocity of this cell, in cells per tick (positive is to the right)
+    pub vx: f32,
+    /// The vertical velocity of this cell, in cells per tick (positive is downwards)
+    pub vy: f32,
+    /// The number of ticks left before this cell dissipates, or 0 if it lasts forever
+    pub lifetime: u16,
+    /// The number of ticks left until a conductive cell can be sparked again, or 0 if it can be sparked now
+    /// The cell carries the spark while this is above SPARK_REFRACTORY_PERIOD, and then rests until it reaches 0
+    pub charge: u8,
+    /// The material that an emitter (eg. a tap) makes copies of, or Empty if it hasn't touched anything yet
+    pub emits: CellType,
 }
 
 /// This implementation block adds methods to the Cell struct
@@ -224,36 +294,105 @@ impl Cell {
             cell_type: CellType::Empty,
             generation: 0,               // Starting generation
             temp: 20.0,                  // Room temperature in Celsius
+            vx: 0.0,                     // Not moving
+            vy: 0.0,
+            lifetime: 0,                 // Lasts forever
+            charge: 0,                   // No spark
+            emits: CellType::Empty,      // Doesn't emit anything
         }
     }
 
     /// Creates a cell with a random type
     /// The #[allow(dead_code)] attribute silences warnings about this function not being used
     #[allow(dead_code)]
-    pub fn random() -> Cell {
+    pub fn random(rng: &mut dyn Random) -> Cell {
         Cell {
-            cell_type: CellType::random(),   // Use the random() function from CellType
+            cell_type: CellType::random(rng), // Use the random() function from CellType
             temp: 20.0,                      // Room temperature
             generation: 0,                   // Starting generation
+            vx: 0.0,                         // Not moving
+            vy: 0.0,
+            lifetime: 0,                     // Lasts forever
+            charge: 0,                       // No spark
+            emits: CellType::Empty,          // Doesn't emit anything
         }
     }
 
     /// Initializes a cell with a specific type, setting appropriate properties
-    pub fn init(&mut self, cell_type: CellType) {
+    /// The random number generator is used to vary the starting temperature of some types
+    pub fn init(&mut self, cell_type: CellType, rng: &mut dyn Random) {
         self.cell_type = cell_type;
+        // New cells start out at rest, with no spark, and emitters don't know what to emit until something touches them
+        self.vx = 0.0;
+        self.vy = 0.0;
+        self.charge = 0;
+        self.emits = CellType::Empty;
+
+        // Set the initial temperature somewhere in the range for this cell type (eg. lava is 1000-2000°C)
+        let (low, high) = CellType::get_properties(cell_type).initial_temp;
+        self.temp = if high > low { low + rng.rand() as f32 * (high - low) } else { low };
+        self.start_lifetime(rng);
+    }
+
+    /// Sets how long this cell lasts before it dissipates, which is somewhere in the range for its type
+    /// This is done whenever a cell becomes a new type, including when it changes state (eg. water boiling into steam)
+    pub fn start_lifetime(&mut self, rng: &mut dyn Random) {
+        self.lifetime = random_lifetime(self.get_properties().lifetime, rng);
+    }
+
+    /// Checks if this cell is carrying a spark (rather than resting after one, or not having one at all)
+    pub fn is_sparking(&self) -> bool {
+        self.charge > SPARK_REFRACTORY_PERIOD
+    }
 
-        // Set initial temperature based on cell type
-        match cell_type {
-            CellType::Lava => self.temp = 1000.0 + rand() as f32 * 1000.0,  // Lava is very hot (1000-2000°C)
-            CellType::Fire => self.temp = rand() as f32 * 1000.0,           // Fire varies in temperature (0-1000°C)
-            _ => self.temp = 20.0,                                          // Everything else starts at room temperature
+    /// Sends a spark through this cell, if it conducts electricity and isn't still resting after the last spark
+    /// Returns true if the cell was sparked
+    pub fn spark(&mut self) -> bool {
+        if self.charge == 0 && self.get_properties().conductive {
+            self.charge = SPARK_DURATION + SPARK_REFRACTORY_PERIOD;
+            return true;
         }
+        false
     }
 
     /// Gets the properties for this cell's type
     /// Convenience method that delegates to CellType::get_properties
-    pub fn get_properties<'a>(&self) -> &'a CellTypeProperties {
+    pub fn get_properties(&self) -> &'static CellTypeProperties {
         CellType::get_properties(self.cell_type)
     }
 }
 
+/// Picks a random number of ticks from the given range (including both ends)
+/// The size of the range is worked out in u32, since a range covering every u16 has one more value than fits in a u16
+fn random_lifetime((shortest, longest): (u16, u16), rng: &mut dyn Random) -> u16 {
+    if longest > shortest {
+        let values = (longest - shortest) as u32 + 1;
+        shortest + (rng.rand() * values as f64) as u16
+    } else {
+        shortest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A random number generator that always gives the same number
+    struct Always(f64);
+
+    impl Random for Always {
+        fn rand(&mut self) -> f64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn lifetimes_cover_the_whole_range() {
+        let almost_one = 1.0 - f64::EPSILON;
+        assert_eq!(random_lifetime((0, 65535), &mut Always(0.0)), 0);
+        assert_eq!(random_lifetime((0, 65535), &mut Always(almost_one)), 65535);
+        assert_eq!(random_lifetime((100, 300), &mut Always(almost_one)), 300);
+        assert_eq!(random_lifetime((100, 100), &mut Always(0.5)), 100);
+        assert_eq!(random_lifetime((0, 0), &mut Always(0.5)), 0);
+    }
+}
@@ -0,0 +1,111 @@
+//! This file defines the forces that push the cells around, like gravity and wind.
+//! The simulators look up these forces when deciding which way a cell should move.
+//! It also keeps the temperature of the air, which the cells warm up or cool down towards.
+
+/// How strong gravity is by default (in cells per tick, per tick)
+pub const DEFAULT_GRAVITY: f32 = 0.2;
+/// The temperature of the air by default (in degrees)
+pub const DEFAULT_AMBIENT_TEMP: f32 = 20.0;
+/// The radius (in cells) of the area that gets painted when the user paints a force
+const PAINT_RADIUS: i32 = 4;
+
+/// The 8 directions a cell can move in, in clockwise order starting from the right
+/// (y increases downwards, so clockwise on the screen is the direction of increasing angle)
+const DIRECTIONS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+
+/// The ForceField holds all the forces acting on the cells of a space
+/// Gravity pulls on everything in the same direction, while the wind and painted forces push cells
+/// along with them (mostly light things like gases)
+pub struct ForceField {
+    /// The direction and strength of gravity, in cells per tick, per tick (positive y is downwards)
+    pub gravity: (f32, f32),
+    /// The direction and strength of the wind that blows everywhere, in cells per tick
+    pub wind: (f32, f32),
+    /// The temperature that empty space (air) slowly returns to, so that heat can escape the simulation
+    /// Making it colder than freezing is what lets water freeze
+    pub ambient_temp: f32,
+    /// Width of the space the forces apply to
+    width: u32,
+    /// Height of the space the forces apply to
+    height: u32,
+    /// Extra forces painted onto parts of the space by the user (one per cell, added to the wind)
+    /// This stays empty until something is painted, so it doesn't cost anything when it isn't used
+    painted: Vec<(f32, f32)>,
+}
+
+impl ForceField {
+    /// Creates a new force field for a space of the given size, with normal gravity, no wind, and air at room temperature
+    pub fn new(width: u32, height: u32) -> ForceField {
+        ForceField {
+            gravity: (0.0, DEFAULT_GRAVITY), // Straight down
+            wind: (0.0, 0.0),                // Calm
+            ambient_temp: DEFAULT_AMBIENT_TEMP, // Room temperature
+            width,
+            height,
+            painted: vec![],                 // Nothing painted yet
+        }
+    }
+
+    /// Returns the one of the 8 directions that's closest to the direction of gravity,
+    /// or None if there's no gravity at all
+    pub fn gravity_direction(&self) -> Option<(i32, i32)> {
+        let (gx, gy) = self.gravity;
+        if gx == 0.0 && gy == 0.0 {
+            return None;
+        }
+
+        // Divide the circle into 8 slices and pick the slice that the angle of gravity falls into
+        let slice = (gy.atan2(gx) / std::f32::consts::FRAC_PI_4).round() as i32;
+        Some(DIRECTIONS[slice.rem_euclid(8) as usize])
+    }
+
+    /// Flips gravity so that it pulls the opposite way
+    pub fn reverse_gravity(&mut self) {
+        self.gravity = (-self.gravity.0, -self.gravity.1);
+    }
+
+    /// Turns the direction of gravity clockwise (on the screen) by the given number of degrees
+    pub fn rotate_gravity(&mut self, degrees: f32) {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (gx, gy) = self.gravity;
+        self.gravity = (gx * cos - gy * sin, gx * sin + gy * cos);
+    }
+
+    /// Returns the force pushing on the cell at the given position (the wind plus anything painted there)
+    pub fn force_at(&self, x: i32, y: i32) -> (f32, f32) {
+        match self.painted.get((y * self.width as i32 + x) as usize) {
+            Some((px, py)) => (self.wind.0 + px, self.wind.1 + py),
+            None => self.wind,
+        }
+    }
+
+    /// Paints the given force onto a small circle around the given position
+    /// The painted force replaces whatever was painted there before
+    pub fn paint(&mut self, x: i32, y: i32, force: (f32, f32)) {
+        if self.painted.is_empty() {
+            self.painted = vec![(0.0, 0.0); (self.width * self.height) as usize];
+        }
+
+        for dy in -PAINT_RADIUS..=PAINT_RADIUS {
+            for dx in -PAINT_RADIUS..=PAINT_RADIUS {
+                let (px, py) = (x + dx, y + dy);
+                if dx * dx + dy * dy <= PAINT_RADIUS * PAINT_RADIUS
+                    && px >= 0 && py >= 0 && px < self.width as i32 && py < self.height as i32 {
+                    self.painted[(py * self.width as i32 + px) as usize] = force;
+                }
+            }
+        }
+    }
+
+    /// Removes all the painted forces, leaving just gravity and the wind
+    pub fn clear_painted(&mut self) {
+        self.painted = vec![];
+    }
+}
+
+/// Turns one of the 8 directions by the given number of eighths of a circle (clockwise on the screen)
+/// For example, turning straight down by one step gives diagonally down and to the left
+pub fn rotate_direction(direction: (i32, i32), steps: i32) -> (i32, i32) {
+    let index = DIRECTIONS.iter().position(|d| *d == direction).unwrap_or(0) as i32;
+    DIRECTIONS[(index + steps).rem_euclid(8) as usize]
+}
@@ -0,0 +1,208 @@
+//! This file keeps track of the changes the user makes to the cells, so that they can be undone and redone.
+//! Each brush stroke (from when the mouse button is pressed until it's released) is one edit, which records
+//! what each cell it changed was before and after the stroke.  Only the user's changes are recorded, and not
+//! the simulation itself, so undoing a stroke puts back the cells it changed, but the rest of the space
+//! carries on from wherever the simulation has got to.  Cells are put back by their position, so anything
+//! that has moved since it was placed (eg. sand that has fallen) stays where it is now.
+
+use std::collections::HashMap;
+
+use crate::cells::Cell;
+use crate::space::Space;
+
+/// The most edits that are kept to be undone (the oldest ones are forgotten first)
+const MAX_UNDO: usize = 100;
+
+/// A change to one cell
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct CellChange {
+    /// The index of the cell in the space
+    index: usize,
+    /// The cell before it was changed
+    before: Cell,
+    /// The cell after it was changed
+    after: Cell,
+}
+
+/// All the changes made by one brush stroke
+#[derive(Clone, Debug, Default)]
+struct Edit {
+    /// The stroke that made these changes (see InputTracker::get_stroke())
+    stroke: u32,
+    /// The cells that were changed, in the order they were first changed
+    changes: Vec<CellChange>,
+    /// The position of each changed cell in the changes list, by its index in the space
+    positions: HashMap<usize, usize>,
+}
+
+/// The History struct keeps the edits that can be undone and redone
+pub struct History {
+    /// The edits that can be undone, from oldest to newest
+    undo: Vec<Edit>,
+    /// The edits that have been undone and can be redone, from newest to oldest
+    redo: Vec<Edit>,
+    /// The edit for the stroke that's still being drawn (if any)
+    current: Option<Edit>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    /// Creates a new, empty history
+    pub fn new() -> History {
+        History {
+            undo: vec![],
+            redo: vec![],
+            current: None,
+        }
+    }
+
+    /// Records that the given cells were changed as part of the given stroke
+    /// Each change is the index of the cell and what it was before it was changed, and the space is used to
+    /// get what it is now.  A cell that's changed more than once in the same stroke keeps what it was before
+    /// the first change
+    pub fn record(&mut self, stroke: u32, space: &Space, changed: Vec<(usize, Cell)>) {
+        if self.current.as_ref().map(|edit| edit.stroke != stroke).unwrap_or(false) {
+            self.finish();
+        }
+        let edit = self.current.get_or_insert_with(|| Edit { stroke, ..Edit::default() });
+
+        for (index, before) in changed {
+            let after = *space.get_cell_ref_at(index);
+            match edit.positions.get(&index) {
+                Some(&position) => edit.changes[position].after = after,
+                None => {
+                    edit.positions.insert(index, edit.changes.len());
+                    edit.changes.push(CellChange { index, before, after });
+                },
+            }
+        }
+    }
+
+    /// Finishes the stroke that's being drawn (if any), so that it can be undone
+    /// Starting a new edit means the edits that were undone can't be redone anymore
+    pub fn finish(&mut self) {
+        if let Some(edit) = self.current.take() {
+            if !edit.changes.is_empty() {
+                self.undo.push(edit);
+                self.redo.clear();
+                if self.undo.len() > MAX_UNDO {
+                    self.undo.remove(0);
+                }
+            }
+        }
+    }
+
+    /// Puts the cells changed by the last edit back to what they were before it
+    /// Returns false if there was nothing to undo
+    pub fn undo(&mut self, space: &mut Space) -> bool {
+        self.finish();
+        match self.undo.pop() {
+            Some(edit) => {
+                for change in edit.changes.iter() {
+                    space.set_cell(change.index, &change.before);
+                }
+                self.redo.push(edit);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Makes the changes in the last edit that was undone again
+    /// Returns false if there was nothing to redo
+    pub fn redo(&mut self, space: &mut Space) -> bool {
+        self.finish();
+        match self.redo.pop() {
+            Some(edit) => {
+                for change in edit.changes.iter() {
+                    space.set_cell(change.index, &change.after);
+                }
+                self.undo.push(edit);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Forgets all the edits (eg. when a different space is loaded, which the edits don't apply to)
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.current = None;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::cells::CellType;
+    use crate::space::Space;
+    use crate::world::World;
+
+    /// Draws a brush stroke of rock, moving the mouse across the space over several ticks
+    fn draw_stroke(world: &mut World, y: i32) {
+        world.input.update_selected_type(CellType::Rock);
+        world.input.update_down(true);
+        for x in [5, 10, 15] {
+            world.input.update_pos(x, y);
+            world.advance_simulation();
+        }
+        world.input.update_down(false);
+        world.advance_simulation();
+    }
+
+    /// Counts the cells of rock in the world
+    fn count_rock(world: &World) -> usize {
+        (0..world.space.get_height())
+            .flat_map(|y| (0..world.space.get_width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| world.space.get_cell_type(x, y) == CellType::Rock)
+            .count()
+    }
+
+    #[test]
+    fn a_stroke_is_undone_in_one_step() {
+        let mut world = World::with_seed(20, 20, 1);
+        draw_stroke(&mut world, 10);
+        let rock = count_rock(&world);
+        assert!(rock > 0);
+
+        // Everything drawn over all the ticks of the stroke is undone at once, and nothing is left to undo
+        assert!(world.undo());
+        assert_eq!(count_rock(&world), 0);
+        assert!(!world.undo());
+
+        assert!(world.redo());
+        assert_eq!(count_rock(&world), rock);
+        assert!(!world.redo());
+    }
+
+    #[test]
+    fn a_new_stroke_clears_redo() {
+        let mut world = World::with_seed(20, 20, 1);
+        draw_stroke(&mut world, 5);
+        assert!(world.undo());
+
+        draw_stroke(&mut world, 15);
+        assert!(!world.redo());
+        // Only the new stroke can be undone
+        assert!(world.undo());
+        assert!(!world.undo());
+    }
+
+    #[test]
+    fn setting_the_space_clears_history() {
+        let mut world = World::with_seed(20, 20, 1);
+        draw_stroke(&mut world, 5);
+        draw_stroke(&mut world, 15);
+        assert!(world.undo());
+
+        world.set_space(Space::new(20, 20));
+        assert!(!world.undo());
+        assert!(!world.redo());
+    }
+}
@@ -0,0 +1,149 @@
+//! This file converts spaces to and from PNG images, so that scenes can be drawn in any image editor, and
+//! shared as pictures that can also be loaded back in.
+//!
+//! Each pixel of the image is one cell, coloured in the same colour that the cell is drawn in on the
+//! screen (the colour given to its material in materials.txt).  When an image is loaded, each pixel becomes
+//! the material whose colour is closest to the pixel's colour, so an image doesn't have to use exactly the
+//! right colours (eg. a slightly different blue still becomes water).  Transparent pixels become empty space.
+//! Only the materials are kept in an image, so the loaded cells start out at the normal temperature for
+//! their material.  Use Space::save() to keep everything.
+
+use std::collections::HashMap;
+
+// Import the cells, and the space they're kept in
+use crate::cells::{ CellType, CELL_TYPES };
+use crate::space::{ Space, LoadError, MAX_LOADED_CELLS };
+// Import the random number generator trait
+use crate::random::Random;
+
+/// Converts the space into a PNG image, with one pixel for each cell
+pub fn save_png(space: &Space) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((space.get_width() * space.get_height() * 3) as usize);
+    for y in 0..space.get_height() {
+        for x in 0..space.get_width() {
+            let (r, g, b) = CellType::get_properties(space.get_cell_type(x, y)).colour;
+            pixels.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    let mut data = vec![];
+    let mut encoder = png::Encoder::new(&mut data, space.get_width(), space.get_height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // Writing into memory can't fail, and the image is always the size given in the header
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&pixels).unwrap();
+    writer.finish().unwrap();
+    data
+}
+
+/// Creates a new space from a PNG image, with one cell for each pixel
+/// Each pixel becomes the material with the closest colour (or empty space if it's transparent), and the random
+/// number generator is used to start the cells off the same way as cells placed with the mouse
+pub fn load_png(data: &[u8], rng: &mut dyn Random) -> Result<Space, LoadError> {
+    let error = |err: png::DecodingError| LoadError { message: format!("the image couldn't be read ({})", err) };
+
+    // Have every kind of PNG (eg. greyscale or with a palette) converted into 8 bits for each colour
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(error)?;
+    let (width, height) = (reader.info().width, reader.info().height);
+    // Images are limited to the same number of cells as saved spaces, so that a huge image can't make us run out of memory
+    if width as u64 * height as u64 > MAX_LOADED_CELLS {
+        return Err(LoadError { message: format!("the image is too big ({} by {})", width, height) });
+    }
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut pixels).map_err(error)?;
+
+    let mut space = Space::new(width, height);
+    // Images tend to only use a few colours, so remember the material chosen for each one
+    let mut matches = HashMap::new();
+    for (i, pixel) in pixels[..frame.buffer_size()].chunks_exact(frame.color_type.samples()).enumerate() {
+        let (r, g, b, a) = match *pixel {
+            [grey] => (grey, grey, grey, 255),
+            [grey, a] => (grey, grey, grey, a),
+            [r, g, b] => (r, g, b, 255),
+            [r, g, b, a] => (r, g, b, a),
+            _ => unreachable!(),
+        };
+        let cell_type = if a < 128 {
+            CellType::Empty
+        } else {
+            *matches.entry((r, g, b)).or_insert_with(|| nearest_cell_type((r, g, b)))
+        };
+        if cell_type != CellType::Empty {
+            space.get_cell_at(i).init(cell_type, rng);
+        }
+    }
+    Ok(space)
+}
+
+/// Finds the material whose colour is the closest to the given colour (including empty space, which is white)
+/// The distance between two colours is measured as if red, green and blue were the 3 dimensions of a cube
+fn nearest_cell_type((r, g, b): (u8, u8, u8)) -> CellType {
+    let distance = |cell_type: &CellType| {
+        let (cr, cg, cb) = CellType::get_properties(*cell_type).colour;
+        let (dr, dg, db) = (r as i32 - cr as i32, g as i32 - cg as i32, b as i32 - cb as i32);
+        dr * dr + dg * dg + db * db
+    };
+    *CELL_TYPES.iter().min_by_key(|cell_type| distance(cell_type)).unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRandom;
+
+    /// Makes a PNG image of the given size from the given pixels (red, green, blue and alpha)
+    fn rgba_png(width: u32, height: u32, pixels: &[[u8; 4]]) -> Vec<u8> {
+        let mut data = vec![];
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&pixels.concat()).unwrap();
+        writer.finish().unwrap();
+        data
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        // A space with every material in it
+        let mut rng = SeededRandom::new(4);
+        let mut space = Space::new(CELL_TYPES.len() as u32, 2);
+        for (i, cell_type) in CELL_TYPES.iter().enumerate() {
+            space.get_cell_at(i).init(*cell_type, &mut rng);
+        }
+
+        let loaded = load_png(&save_png(&space), &mut rng).unwrap();
+        assert_eq!((loaded.get_width(), loaded.get_height()), (space.get_width(), space.get_height()));
+        for y in 0..space.get_height() {
+            for x in 0..space.get_width() {
+                assert_eq!(loaded.get_cell_type(x, y), space.get_cell_type(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn off_palette_colours_become_the_nearest_material() {
+        let pixels = [
+            [10, 10, 240, 255],     // Nearly blue, so water
+            [8, 4, 0, 255],         // Nearly black, so rock
+            [250, 250, 250, 255],   // Nearly white, so empty space
+            [255, 51, 0, 100],      // The colour of fire, but mostly transparent, so empty space
+        ];
+        let space = load_png(&rgba_png(4, 1, &pixels), &mut SeededRandom::new(4)).unwrap();
+        assert_eq!(space.get_cell_type(0, 0), CellType::Water);
+        assert_eq!(space.get_cell_type(1, 0), CellType::Rock);
+        assert_eq!(space.get_cell_type(2, 0), CellType::Empty);
+        assert_eq!(space.get_cell_type(3, 0), CellType::Empty);
+    }
+
+    #[test]
+    fn rejects_images_bigger_than_a_saved_space_can_be() {
+        // One pixel wider than the most cells a space can have, which is refused before the pixels are read
+        let data = rgba_png(MAX_LOADED_CELLS as u32 + 1, 1, &vec![[0, 0, 0, 0]; MAX_LOADED_CELLS as usize + 1]);
+        assert!(load_png(&data, &mut SeededRandom::new(4)).is_err());
+    }
+}
@@ -14,6 +14,16 @@ pub struct InputTracker {
     y: i32,
     /// The type of cell the user wants to place
     selected_type: CellType,
+    /// The force the user wants to paint instead of placing cells (if any)
+    selected_force: Option<(f32, f32)>,
+    /// Counts the brush strokes, with a new one starting each time the mouse button is pressed
+    stroke: u32,
+}
+
+impl Default for InputTracker {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl InputTracker {
@@ -24,11 +34,17 @@ impl InputTracker {
             x: 0,                     // Initial X position
             y: 0,                     // Initial Y position
             selected_type: CellType::Sand, // Default to Sand cell type
+            selected_force: None,     // Place cells rather than painting forces
+            stroke: 0,                // No strokes yet
         }
     }
 
     /// Updates whether the mouse button is pressed or not
+    /// Pressing the button starts a new brush stroke, which lasts until it's released
     pub fn update_down(&mut self, down: bool) {
+        if down && !self.down {
+            self.stroke = self.stroke.wrapping_add(1);
+        }
         self.down = down;
     }
 
@@ -41,6 +57,12 @@ impl InputTracker {
     /// Changes the selected cell type that will be placed when clicking
     pub fn update_selected_type(&mut self, cell_type: CellType) {
         self.selected_type = cell_type;
+        self.selected_force = None;
+    }
+
+    /// Changes to painting the given force when clicking, instead of placing cells
+    pub fn update_selected_force(&mut self, force: (f32, f32)) {
+        self.selected_force = Some(force);
     }
 
     /// Returns whether the mouse button is currently down
@@ -58,9 +80,20 @@ impl InputTracker {
         }
     }
 
+    /// Returns the number of the current brush stroke (or the last one, if the mouse button isn't pressed)
+    /// Each stroke can be undone as a whole
+    pub fn get_stroke(&self) -> u32 {
+        self.stroke
+    }
+
     /// Returns the currently selected cell type
     pub fn get_selected_type(&self) -> CellType {
         self.selected_type
     }
+
+    /// Returns the force to paint, if the user has chosen to paint forces rather than place cells
+    pub fn get_selected_force(&self) -> Option<(f32, f32)> {
+        self.selected_force
+    }
 }
 
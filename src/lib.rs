@@ -3,35 +3,55 @@
 
 // Import standard library components we need:
 /// Rc (Reference Counted) allows multiple parts of our code to share ownership of data
+#[cfg(feature = "web")]
 use std::rc::Rc;
 /// RefCell provides interior mutability - a way to mutate data even when there are multiple references to it
+#[cfg(feature = "web")]
 use std::cell::RefCell;
 
 // Import WebAssembly bindings which allow our Rust code to interact with JavaScript
+#[cfg(feature = "web")]
 use wasm_bindgen::prelude::*;
 
 // These 'mod' declarations tell Rust about our other code modules.
 // Each module is in its own separate file with the same name.
+// The simulation core is public and has no browser dependencies, so it can be built, tested,
+// and benchmarked natively with `cargo test --no-default-features`
 /// Defines the different types of cells/particles in our simulation
-mod cells;
+pub mod cells;
+/// Reads the properties of every material (cell type) from a data file
+pub mod materials;
 /// Manages the grid where all our cells live
-mod space;
+pub mod space;
+/// Converts spaces to and from PNG images
+pub mod image;
 /// Handles user inputs like mouse clicks and movements
-mod input;
+pub mod input;
 /// Represents the entire game world state
-mod world;
+pub mod world;
+/// Keeps track of the user's changes, so they can be undone
+pub mod history;
 /// Contains the core simulation logic
-mod simulator;
-/// Handles the user interface elements
+pub mod simulator;
+/// Defines the forces (gravity and wind) that push the cells around
+pub mod forces;
+/// Reads the rules for the cellular automaton simulator from text
+pub mod rules;
+/// Provides the seedable random number generator used by the simulation
+pub mod random;
+/// Handles the user interface elements (only available with the "web" feature)
+#[cfg(feature = "web")]
 mod ui;
+/// Keeps spaces in the browser's local storage (only available with the "web" feature)
+#[cfg(feature = "web")]
+mod storage;
 
-// This attribute tells the Rust compiler not to warn about unused code in the timer module
-#[allow(dead_code)]
 /// Provides timing functionality for the simulation
-mod timer;
+pub mod timer;
 
 // This imports the World struct from our world module, making it accessible in this file
-use crate::world::World;
+#[cfg(feature = "web")]
+use crate::world::{ World, CUSTOM_RULES };
 
 /// Controls how often the simulation updates (in milliseconds)
 pub static REFRESH: i32 = 10;
@@ -45,19 +65,33 @@ pub static CELL_WIDTH: u32 = 3;
 pub static CELL_HEIGHT: u32 = 3;
 
 /// The #[wasm_bindgen] attribute exposes the following definitions to JavaScript
+#[cfg(feature = "web")]
 #[wasm_bindgen]
 extern {
     /// Allows our Rust code to trigger browser alerts
     fn alert(s: &str);
-    
+}
+
+/// The browser's console only exists when running as WebAssembly, so this is kept separate from the
+/// definitions above, which are also needed (but never called) when testing natively
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+#[wasm_bindgen]
+extern {
     /// Allows our Rust code to write messages to the browser's console
     /// The js_namespace attribute specifies which JavaScript object the function belongs to
     #[wasm_bindgen(js_namespace = console)]
     fn log(s: &str);
 }
 
+/// Without the browser, log messages are written to stderr instead of the console
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+pub fn log(s: &str) {
+    eprintln!("{}", s);
+}
+
 /// This is the main entry point function that will be called from JavaScript
 /// when our application starts
+#[cfg(feature = "web")]
 #[wasm_bindgen]
 pub fn run() {
     // Log a welcome message to the browser console
@@ -68,15 +102,188 @@ pub fn run() {
     // while still being able to modify it
     let world = Rc::new(RefCell::new(World::new(SPACE_WIDTH, SPACE_HEIGHT)));
 
+    // Log the random seed, so that a run can be reproduced from a bug report
+    log(&format!("Random seed: {}", world.borrow().get_seed()));
+
+    // Keep a reference to the world for the functions that JavaScript can call
+    WORLD.with(|global| *global.borrow_mut() = Some(world.clone()));
+
+    // If the page was opened from a shared link, load the space that's in the link
+    let shared = match storage::load_shared_link() {
+        Some(Ok(space)) => {
+            world.borrow_mut().set_space(space);
+            true
+        },
+        Some(Err(err)) => {
+            alert(&format!("Couldn't load the space from the link: {}", err));
+            false
+        },
+        None => false,
+    };
+
+    // Otherwise, pick up where we left off last time (if the page has been opened before), and keep saving from now on
+    storage::start_autosave(world.clone(), !shared);
+
     // Initialize the DOM (Document Object Model) for our user interface
     // We pass our world object so the UI can interact with it
     ui::init_dom(world);
 }
 
-/// A helper function that provides a random number generator
-/// This calls JavaScript's Math.random() function to get a random float between 0 and 1
-pub fn rand() -> f64 {
-    js_sys::Math::random()
+// The world created by run() is kept here, so that the functions exported to JavaScript can get to it
+#[cfg(feature = "web")]
+thread_local! {
+    static WORLD: RefCell<Option<Rc<RefCell<World>>>> = const { RefCell::new(None) };
+}
+
+/// Calls the given function with the world created by run()
+/// Returns None without calling it if run() hasn't been called yet
+#[cfg(feature = "web")]
+fn with_world<R, F>(f: F) -> Option<R>
+    where F: FnOnce(&mut World) -> R {
+    WORLD.with(|global| global.borrow().as_ref().map(|world| f(&mut world.borrow_mut())))
+}
+
+/// Returns the names of all the simulators that can be chosen with set_simulator() (including the custom rules,
+/// once some have been loaded with load_rules())
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn get_simulators() -> js_sys::Array {
+    let custom = with_world(|world| world.has_custom_rules()).unwrap_or(false);
+    simulator::SIMULATORS.iter()
+        .map(|(name, _)| *name)
+        .chain(if custom { Some(CUSTOM_RULES) } else { None })
+        .map(JsValue::from_str)
+        .collect()
+}
+
+/// Returns the name of the simulator being used (or undefined if the world hasn't been created yet)
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn get_simulator() -> Option<String> {
+    with_world(|world| world.get_simulator_name().to_string())
+}
+
+/// Switches to the simulator with the given name, keeping all the cells
+/// Returns false if there's no simulator with that name (or the world hasn't been created yet)
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn set_simulator(name: &str) -> bool {
+    with_world(|world| {
+        let selected = world.select_simulator(name);
+        ui::update_simulator_buttons(world);
+        selected
+    }).unwrap_or(false)
+}
+
+/// Switches to the cellular automaton simulator, using rules written as text instead of the built-in ones
+/// (see rules.rs for how to write them).  All the cells are kept, and the rules can be chosen again later
+/// with set_simulator("Custom Rules")
+/// Throws an error saying what's wrong (and on which line) if the rules can't be read
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn load_rules(text: &str) -> Result<(), JsValue> {
+    with_world(|world| {
+        let loaded = world.load_rules(text);
+        ui::update_simulator_buttons(world);
+        loaded
+    }).unwrap_or(Ok(()))
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+/// Saves all the cells in the world, so they can be downloaded as a file and loaded again with load_world()
+/// Returns undefined if the world hasn't been created yet
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn save_world() -> Option<Vec<u8>> {
+    with_world(|world| world.save())
+}
+
+/// Replaces all the cells in the world with ones saved by save_world()
+/// Throws an error saying what's wrong if the file can't be loaded (and leaves the world as it was)
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn load_world(data: &[u8]) -> Result<(), JsValue> {
+    with_world(|world| world.load(data))
+        .unwrap_or(Ok(()))
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+/// Draws all the cells in the world into a PNG image (one pixel for each cell), which can be loaded again with load_png()
+/// Returns undefined if the world hasn't been created yet
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn save_png() -> Option<Vec<u8>> {
+    with_world(|world| world.save_png())
+}
+
+/// Replaces all the cells in the world with ones read from a PNG image, where each pixel becomes the material
+/// with the closest colour.  Throws an error saying what's wrong if the image can't be read (and leaves the world as it was)
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn load_png(data: &[u8]) -> Result<(), JsValue> {
+    with_world(|world| world.load_png(data))
+        .unwrap_or(Ok(()))
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+/// Returns a link to this page with all the cells in the world in it, so the space can be shared
+/// Returns undefined if the world hasn't been created yet
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn share_link() -> Option<String> {
+    with_world(|world| storage::share_link(&world.space))
+}
+
+/// Saves all the cells in the world into the browser's local storage, in the slot with the given name
+/// Throws an error if it couldn't be saved (eg. because there isn't enough room left)
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn save_slot(name: &str) -> Result<(), JsValue> {
+    with_world(|world| storage::save_slot(world, name))
+        .unwrap_or(Ok(()))
+        .map_err(|err| JsValue::from_str(&err))
+}
+
+/// Replaces all the cells in the world with the ones saved in the slot with the given name
+/// Returns false if nothing has been saved in that slot, and throws an error if it couldn't be loaded
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn load_slot(name: &str) -> Result<bool, JsValue> {
+    with_world(|world| storage::load_slot(world, name))
+        .unwrap_or(Ok(false))
+        .map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+/// Deletes whatever is saved in the slot with the given name
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn delete_slot(name: &str) {
+    storage::delete_slot(name);
+}
+
+/// Returns the names of all the slots that have something saved in them
+#[cfg(feature = "web")]
+#[wasm_bindgen]
+pub fn list_slots() -> js_sys::Array {
+    storage::list_slots().iter().map(|name| JsValue::from_str(name)).collect()
+}
+
+/// Picks a seed for the simulation's random number generator
+/// This calls JavaScript's Math.random() function to get two random 32-bit halves
+#[cfg(all(feature = "web", target_arch = "wasm32"))]
+pub fn random_seed() -> u64 {
+    let high = (js_sys::Math::random() * u32::MAX as f64) as u64;
+    let low = (js_sys::Math::random() * u32::MAX as f64) as u64;
+    (high << 32) | low
+}
+
+/// Picks a seed for the simulation's random number generator
+/// When running natively, there is no Math.random(), so the system clock is used instead
+#[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+pub fn random_seed() -> u64 {
+    use std::time::{ SystemTime, UNIX_EPOCH };
+
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0)
 }
 
 /// This macro provides a convenient way to print debug messages to the browser console
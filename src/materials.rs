@@ -0,0 +1,492 @@
+//! This file reads the definitions of all the materials (cell types) from a data file, so that the properties
+//! of a material (how it looks, moves, changes state and reacts) are written in one place instead of being
+//! spread across the simulator, the renderer, and the buttons.  Each material is still a variant of the
+//! `CellType` enum, though, so adding one means changing the code as well (see the top of `materials.txt`).
+//! The definitions are read from `materials.txt` (which is built into the program) the first time they're
+//! needed, and are kept in a registry that's used by the rest of the program through
+//! `CellType::get_properties()` and `CellType::iter()`.
+//!
+//! Each material starts with the word `material` followed by its name, which must be the name of one of
+//! the variants of `CellType`.  The lines after it give the material's properties, one on each line:
+//!
+//! ```text
+//! # Water is a flowing liquid
+//! material Water
+//!     colour 0000FF               # The colour it's drawn in, as 6 hex digits (red, green and blue)
+//!     density 1.0                 # How heavy it is (heavier materials sink below lighter ones)
+//!     movement liquid             # How it moves: static, granular, liquid or gas
+//!     temp-coefficient 0.4        # How quickly it heats up or cools down
+//!     above 100 Steam             # Turns into steam when hotter than 100 degrees
+//!     below 0 Ice                 # Turns into ice when colder than 0 degrees
+//! ```
+//!
+//! The `colour`, `density`, `movement` and `temp-coefficient` must always be given.  The temp-coefficient
+//! must be greater than 0, and so must the density of anything that isn't static.  The other properties
+//! are optional:
+//! - `temp 20` or `temp 1000 2000`, the temperature (or range of temperatures) that new cells start at
+//!   (20 degrees if not given)
+//! - `lifetime 100` or `lifetime 100 300`, the number of ticks (or range of ticks) that a cell lasts before it
+//!   dissipates into empty space (cells last forever if not given)
+//! - `flammable 300`, which means it catches fire when hotter than the given temperature
+//! - `smoke 0.5`, the chance that a flammable material gives off smoke when it catches fire
+//! - `explosive`, which means it explodes instead of burning when it catches fire
+//! - `dissolvable`, which means it can be dissolved by acid
+//! - `conductive`, which means it conducts electricity (sparks travel along it)
+//! - `above <temp> <material>` and `below <temp> <material>`, which are the phase changes (eg. melting)
+//! - `reaction <other> <probability> <becomes> <other-becomes>`, which means that when this material is
+//!   touching the other material, they turn into the given materials with the given probability each tick
+//!
+//! The other material in a reaction can also be `flammable`, `explosive` or `dissolvable`, to react with
+//! any material that has that property, and either of the materials it becomes can be `=`, to leave that
+//! cell as it was.  A material that's turned into Fire catches fire (so an explosive material explodes).
+//! A reaction can be followed by any of these, to limit when it happens or to make it give off heat:
+//! - `above <temp>` and `below <temp>`, which mean it only happens when this material is hotter (or colder)
+//!   than the given temperature
+//! - `heat <amount>`, which adds the given amount of heat to both cells (or takes it away, if it's negative)
+//!
+//! For example, this makes acid dissolve anything that can be dissolved (using up the acid), and makes
+//! ice pack the snow around it into more ice, as long as the ice is below freezing:
+//!
+//! ```text
+//! material Acid
+//!     ...
+//!     reaction dissolvable 0.005 Empty Empty
+//!
+//! material Ice
+//!     ...
+//!     reaction Snow 0.001 = Ice below 0
+//! ```
+//!
+//! Reactions aren't used for setting things on fire.  A flammable material catches fire when its own
+//! temperature reaches its `flammable` temperature, which happens when it's heated by the things around it.
+//!
+//! Blank lines are ignored, and anything after a `#` is a comment.  Every `CellType` must have exactly
+//! one material, and the buttons for placing materials are shown in the order they're written in.  This
+//! means a new material needs a new `CellType` as well (see the top of `materials.txt` for everything that
+//! has to be changed).
+
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::cells::{ CellType, CellTypeProperties, Movement, PhaseChange, Reaction, Reactant, CELL_TYPES };
+
+/// The definitions of all the materials, which are built into the program
+const MATERIALS_TEXT: &str = include_str!("materials.txt");
+
+/// The registry of materials, which is filled in from MATERIALS_TEXT the first time it's used
+static REGISTRY: OnceLock<MaterialRegistry> = OnceLock::new();
+
+/// All the materials that were read in from the definitions
+pub struct MaterialRegistry {
+    /// The properties of each cell type, in the same order as the CellType enum (so they can be looked up quickly)
+    properties: Vec<CellTypeProperties>,
+    /// All the cell types except Empty, in the order they were written in
+    order: Vec<CellType>,
+}
+
+impl MaterialRegistry {
+    /// Returns the registry of materials, reading in the definitions if they haven't been read yet
+    /// The definitions are built into the program, so a mistake in them is a bug and stops the program
+    #[inline]
+    pub fn get() -> &'static MaterialRegistry {
+        REGISTRY.get_or_init(|| {
+            parse_materials(MATERIALS_TEXT).unwrap_or_else(|err| panic!("error in materials.txt on {}", err))
+        })
+    }
+
+    /// Returns the properties of the given cell type
+    #[inline]
+    pub fn get_properties(&self, cell_type: CellType) -> &CellTypeProperties {
+        &self.properties[cell_type as usize]
+    }
+
+    /// Returns all the cell types except Empty, in the order they were written in
+    pub fn cell_types(&self) -> &[CellType] {
+        &self.order
+    }
+}
+
+/// An error found while reading the materials, along with the line it was found on
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MaterialError {
+    /// The line number the problem was found on (starting from 1)
+    pub line: usize,
+    /// A description of the problem
+    pub message: String,
+}
+
+impl MaterialError {
+    /// Creates a new error for the given line
+    fn new(line: usize, message: String) -> MaterialError {
+        MaterialError {
+            line,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for MaterialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for MaterialError { }
+
+/// The properties of a material that's still being read in, which are filled in as each line is read
+/// The ones that must be given are optional here, so it can be checked that they were given
+struct PartialMaterial {
+    name: String,
+    cell_type: CellType,
+    line: usize,
+    colour: Option<(u8, u8, u8)>,
+    density: Option<f64>,
+    /// The line the density was given on (so a density that doesn't suit the movement can be reported there)
+    density_line: usize,
+    movement: Option<Movement>,
+    temp_coefficient: Option<f32>,
+    initial_temp: (f32, f32),
+    lifetime: (u16, u16),
+    flammable: bool,
+    ignition_temp: f32,
+    smoke: f64,
+    explosive: bool,
+    dissolvable: bool,
+    conductive: bool,
+    phase_changes: Vec<PhaseChange>,
+    reactions: Vec<Reaction>,
+}
+
+impl PartialMaterial {
+    /// Starts a new material with the default values for the optional properties
+    fn new(name: &str, cell_type: CellType, line: usize) -> PartialMaterial {
+        PartialMaterial {
+            name: name.to_string(),
+            cell_type,
+            line,
+            colour: None,
+            density: None,
+            density_line: line,
+            movement: None,
+            temp_coefficient: None,
+            initial_temp: (20.0, 20.0),     // Room temperature
+            lifetime: (0, 0),               // Lasts forever
+            flammable: false,               // Can't catch fire
+            ignition_temp: 0.0,             // Not used, since it can't catch fire
+            smoke: 0.0,                     // Doesn't give off smoke
+            explosive: false,               // Doesn't explode
+            dissolvable: false,             // Can't be dissolved by acid
+            conductive: false,              // Doesn't conduct electricity
+            phase_changes: vec![],          // Doesn't change state
+            reactions: vec![],              // Doesn't react with anything
+        }
+    }
+
+    /// Checks that all the properties that must be given were given, and returns the finished properties
+    fn finish(self) -> Result<CellTypeProperties, MaterialError> {
+        let missing = |property: &str| MaterialError::new(self.line, format!("the material \"{}\" is missing its {}", self.name, property));
+        let density = self.density.ok_or_else(|| missing("density"))?;
+        let movement = self.movement.ok_or_else(|| missing("movement"))?;
+
+        // Anything that moves has to weigh something, so it can be compared with the things around it
+        if movement != Movement::Static && (!density.is_finite() || density <= 0.0) {
+            return Err(MaterialError::new(self.density_line, format!("the material \"{}\" moves, so its density must be greater than 0", self.name)));
+        }
+
+        Ok(CellTypeProperties {
+            cell_type: self.cell_type,
+            colour: self.colour.ok_or_else(|| missing("colour"))?,
+            density,
+            movement,
+            temp_coefficient: self.temp_coefficient.ok_or_else(|| missing("temp-coefficient"))?,
+            initial_temp: self.initial_temp,
+            lifetime: self.lifetime,
+            flammable: self.flammable,
+            ignition_temp: self.ignition_temp,
+            smoke: self.smoke,
+            explosive: self.explosive,
+            dissolvable: self.dissolvable,
+            conductive: self.conductive,
+            phase_changes: self.phase_changes,
+            reactions: self.reactions,
+            name: self.name,
+        })
+    }
+}
+
+/// Reads all the materials in the given text, or returns an error describing the first problem found
+pub(crate) fn parse_materials(text: &str) -> Result<MaterialRegistry, MaterialError> {
+    let mut materials: Vec<PartialMaterial> = vec![];
+
+    // Number the lines (starting from 1), and skip over comments and blank lines
+    let lines = text.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    for (number, line) in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (keyword, args) = (words[0], &words[1..]);
+
+        if keyword == "material" {
+            let name = one_arg(number, keyword, args)?;
+            let cell_type = parse_cell_type(number, name)?;
+            if materials.iter().any(|material| material.cell_type == cell_type) {
+                return Err(MaterialError::new(number, format!("the material \"{}\" is defined more than once", name)));
+            }
+            materials.push(PartialMaterial::new(name, cell_type, number));
+            continue;
+        }
+
+        // Every other line is a property of the last material that was started
+        let material = materials.last_mut()
+            .ok_or_else(|| MaterialError::new(number, format!("expected the start of a material (eg. \"material Sand\") but found \"{}\"", line)))?;
+        match keyword {
+            "colour" | "color" => {
+                // Colours are written without a # in front, since that would start a comment
+                let colour = one_arg(number, keyword, args)?;
+                if colour.len() != 6 || !colour.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(MaterialError::new(number, format!("the colour \"{}\" must be written as 6 hex digits (eg. FF8800)", colour)));
+                }
+                let component = |i: usize| u8::from_str_radix(&colour[i..i + 2], 16).unwrap();
+                material.colour = Some((component(0), component(2), component(4)));
+            },
+            "density" => {
+                material.density = Some(parse_number(number, one_arg(number, keyword, args)?)?);
+                material.density_line = number;
+            },
+            "temp-coefficient" => {
+                // Heat is passed between cells in proportion to their coefficients, which doesn't work for 0 (or less)
+                let word = one_arg(number, keyword, args)?;
+                let coefficient: f32 = parse_number(number, word)?;
+                if !coefficient.is_finite() || coefficient <= 0.0 {
+                    return Err(MaterialError::new(number, format!("the temp-coefficient \"{}\" must be a number greater than 0", word)));
+                }
+                material.temp_coefficient = Some(coefficient);
+            },
+            "movement" => {
+                let movement = one_arg(number, keyword, args)?;
+                material.movement = Some(match movement.to_ascii_lowercase().as_str() {
+                    "static" => Movement::Static,
+                    "granular" => Movement::Granular,
+                    "liquid" => Movement::Liquid,
+                    "gas" => Movement::Gas,
+                    _ => return Err(MaterialError::new(number, format!("the movement \"{}\" must be static, granular, liquid or gas", movement))),
+                });
+            },
+            "temp" => {
+                material.initial_temp = match args {
+                    [temp] => (parse_number(number, temp)?, parse_number(number, temp)?),
+                    [low, high] => (parse_number(number, low)?, parse_number(number, high)?),
+                    _ => return Err(MaterialError::new(number, "expected a temperature (eg. \"temp 20\") or a range (eg. \"temp 1000 2000\")".to_string())),
+                };
+            },
+            "lifetime" => {
+                material.lifetime = match args {
+                    [ticks] => (parse_number(number, ticks)?, parse_number(number, ticks)?),
+                    [shortest, longest] => (parse_number(number, shortest)?, parse_number(number, longest)?),
+                    _ => return Err(MaterialError::new(number, "expected a number of ticks (eg. \"lifetime 100\") or a range (eg. \"lifetime 100 300\")".to_string())),
+                };
+            },
+            "smoke" => material.smoke = parse_probability(number, one_arg(number, keyword, args)?)?,
+            "flammable" => {
+                material.flammable = true;
+                material.ignition_temp = parse_number(number, one_arg(number, keyword, args)?)?;
+            },
+            "explosive" | "dissolvable" | "conductive" => {
+                if let Some(word) = args.first() {
+                    return Err(MaterialError::new(number, format!("unexpected \"{}\" after \"{}\"", word, keyword)));
+                }
+                match keyword {
+                    "explosive" => material.explosive = true,
+                    "dissolvable" => material.dissolvable = true,
+                    _ => material.conductive = true,
+                }
+            },
+            "above" | "below" => {
+                let (temp, into) = match args {
+                    [temp, into] => (parse_number(number, temp)?, parse_cell_type(number, into)?),
+                    _ => return Err(MaterialError::new(number, format!("expected a temperature and a material (eg. \"{} 100 Steam\")", keyword))),
+                };
+                material.phase_changes.push(if keyword == "above" { PhaseChange::Above(temp, into) } else { PhaseChange::Below(temp, into) });
+            },
+            "reaction" => material.reactions.push(parse_reaction(number, args)?),
+            _ => return Err(MaterialError::new(number, format!("unknown property \"{}\"", keyword))),
+        }
+    }
+
+    // Every cell type needs a material, so that its properties can always be looked up
+    let last_line = text.lines().count().max(1);
+    let mut properties = vec![];
+    for cell_type in CELL_TYPES.iter() {
+        let position = materials.iter().position(|material| material.cell_type == *cell_type)
+            .ok_or_else(|| MaterialError::new(last_line, format!("the material \"{:?}\" is never defined", cell_type)))?;
+        properties.push(materials.remove(position));
+    }
+
+    let mut order: Vec<(usize, CellType)> = properties.iter()
+        .filter(|material| material.cell_type != CellType::Empty)
+        .map(|material| (material.line, material.cell_type))
+        .collect();
+    order.sort_by_key(|(line, _)| *line);
+
+    Ok(MaterialRegistry {
+        properties: properties.into_iter().map(|material| material.finish()).collect::<Result<_, _>>()?,
+        order: order.into_iter().map(|(_, cell_type)| cell_type).collect(),
+    })
+}
+
+/// Reads the words after "reaction" (eg. "Water 0.05 Rock Steam heat 100")
+fn parse_reaction(number: usize, args: &[&str]) -> Result<Reaction, MaterialError> {
+    let (other, probability, becomes, other_becomes, options) = match args {
+        [other, probability, becomes, other_becomes, options @ ..] => (other, probability, becomes, other_becomes, options),
+        _ => return Err(MaterialError::new(number, "expected the other material, the probability, and what each material becomes (eg. \"reaction Water 0.2 Empty Steam\")".to_string())),
+    };
+
+    let other = match other.to_ascii_lowercase().as_str() {
+        "flammable" => Reactant::Flammable,
+        "explosive" => Reactant::Explosive,
+        "dissolvable" => Reactant::Dissolvable,
+        _ => Reactant::Type(parse_cell_type(number, other)?),
+    };
+    let product = |word: &str| match word {
+        "=" => Ok(None),
+        _ => parse_cell_type(number, word).map(Some),
+    };
+
+    let mut reaction = Reaction {
+        other,
+        probability: parse_probability(number, probability)?,
+        temp_range: (f32::NEG_INFINITY, f32::INFINITY),     // Happens at any temperature
+        becomes: product(becomes)?,
+        other_becomes: product(other_becomes)?,
+        heat: 0.0,                                          // Doesn't give off any heat
+    };
+
+    // The options come in pairs of a keyword and a number
+    for option in options.chunks(2) {
+        match option {
+            ["above", temp] => reaction.temp_range.0 = parse_number(number, temp)?,
+            ["below", temp] => reaction.temp_range.1 = parse_number(number, temp)?,
+            ["heat", heat] => reaction.heat = parse_number(number, heat)?,
+            _ => return Err(MaterialError::new(number, format!("unexpected \"{}\" in the reaction (expected \"above\", \"below\" or \"heat\" and a number)", option.join(" ")))),
+        }
+    }
+    Ok(reaction)
+}
+
+/// Returns the one word given after a keyword, or an error if there isn't exactly one
+fn one_arg<'a>(number: usize, keyword: &str, args: &[&'a str]) -> Result<&'a str, MaterialError> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(MaterialError::new(number, format!("expected one value after \"{}\"", keyword))),
+    }
+}
+
+/// Reads a number
+fn parse_number<T: std::str::FromStr>(number: usize, word: &str) -> Result<T, MaterialError> {
+    word.parse().map_err(|_| MaterialError::new(number, format!("\"{}\" isn't a number", word)))
+}
+
+/// Reads a probability, which must be a number from 0 to 1
+fn parse_probability(number: usize, word: &str) -> Result<f64, MaterialError> {
+    match word.parse::<f64>() {
+        Ok(probability) if (0.0..=1.0).contains(&probability) => Ok(probability),
+        _ => Err(MaterialError::new(number, format!("the probability \"{}\" must be a number from 0 to 1", word))),
+    }
+}
+
+/// Finds the cell type with the given name (ignoring upper and lower case)
+/// The registry can't be used for this, because it's the registry that's being read in, so the name
+/// of the CellType variant is used instead
+fn parse_cell_type(number: usize, word: &str) -> Result<CellType, MaterialError> {
+    CELL_TYPES.iter()
+        .find(|cell_type| format!("{:?}", cell_type).eq_ignore_ascii_case(word))
+        .copied()
+        .ok_or_else(|| MaterialError::new(number, format!("unknown material \"{}\" (every material needs a CellType)", word)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the built-in materials with one line changed (the line must appear exactly once)
+    /// Returns the number of the line that was changed, along with the result
+    fn parse_changed(line: &str, replacement: &str) -> (usize, Result<MaterialRegistry, MaterialError>) {
+        assert_eq!(MATERIALS_TEXT.matches(line).count(), 1);
+        let number = MATERIALS_TEXT.lines().position(|text| text.contains(line)).unwrap() + 1;
+        (number, parse_materials(&MATERIALS_TEXT.replacen(line, replacement, 1)))
+    }
+
+    /// Checks that reading the built-in materials with one line changed fails on that line with the given message
+    fn assert_bad_line(line: &str, replacement: &str, message: &str) {
+        match parse_changed(line, replacement) {
+            (number, Err(err)) => {
+                assert_eq!(err.line, number, "{}", err);
+                assert!(err.message.contains(message), "{}", err);
+            },
+            (_, Ok(_)) => panic!("\"{}\" was accepted", replacement),
+        }
+    }
+
+    #[test]
+    fn every_cell_type_has_one_material() {
+        let registry = parse_materials(MATERIALS_TEXT).unwrap();
+        for cell_type in CELL_TYPES.iter() {
+            assert_eq!(registry.get_properties(*cell_type).name, format!("{:?}", cell_type));
+            assert_eq!(registry.get_properties(*cell_type).cell_type, *cell_type);
+        }
+        // Every cell type except Empty gets a button, once each
+        assert_eq!(registry.cell_types().len(), CELL_TYPES.len() - 1);
+        assert!(CELL_TYPES[1..].iter().all(|cell_type| registry.cell_types().contains(cell_type)));
+    }
+
+    #[test]
+    fn flammable_materials_catch_fire_before_they_change_state() {
+        // Otherwise they'd turn into something else on the way up to their ignition temperature, and never burn
+        let registry = parse_materials(MATERIALS_TEXT).unwrap();
+        for cell_type in CELL_TYPES.iter() {
+            let props = registry.get_properties(*cell_type);
+            if props.flammable {
+                assert_eq!(props.phase_change_at(props.ignition_temp), None, "{}", props.name);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_and_repeated_materials() {
+        // Only Empty is defined, so the first one that's missing is reported (on the last line)
+        let err = parse_materials("material Empty\n    colour FFFFFF\n    density 0\n    movement static\n    temp-coefficient 0.1\n").err().unwrap();
+        assert_eq!(err, MaterialError::new(5, "the material \"Rock\" is never defined".to_string()));
+
+        assert_bad_line("material Tap", "material Drain", "is defined more than once");
+    }
+
+    #[test]
+    fn bad_lines() {
+        let line = "    colour FFFFFF               # White";
+        assert_bad_line(line, "    colour FFFFF", "must be written as 6 hex digits");
+        assert_bad_line(line, "    colour", "expected one value after \"colour\"");
+        assert_bad_line(line, "    sparkly", "unknown property \"sparkly\"");
+        assert_bad_line(line, "    movement bouncy", "must be static, granular, liquid or gas");
+        assert_bad_line(line, "    density heavy", "\"heavy\" isn't a number");
+        assert_bad_line(line, "    temp-coefficient 0", "must be a number greater than 0");
+        assert_bad_line(line, "    temp-coefficient -1", "must be a number greater than 0");
+
+        // Anything that moves needs a density, but empty space (which is static) doesn't
+        assert_bad_line("    density 1.0                 # Medium density", "    density 0", "the material \"Water\" moves, so its density must be greater than 0");
+        assert_bad_line(line, "    explosive very", "unexpected \"very\" after \"explosive\"");
+        assert_bad_line(line, "    above 100 Cheese", "unknown material \"Cheese\"");
+        assert_bad_line(line, "    reaction Water 2 Empty Steam", "must be a number from 0 to 1");
+        assert_bad_line(line, "    reaction Water 0.5 Empty", "expected the other material");
+        assert_bad_line(line, "    reaction Water 0.5 Empty Steam hot 100", "unexpected \"hot 100\" in the reaction");
+
+        // Properties have to come after the start of a material
+        assert_bad_line("# The materials (cell types)", "colour FFFFFF #", "expected the start of a material");
+
+        // A missing property is reported on the line that starts the material
+        let (number, result) = parse_changed("    colour FFFFFF               # White", "");
+        assert_eq!(result.err(), Some(MaterialError::new(number - 1, "the material \"Empty\" is missing its colour".to_string())));
+    }
+}
@@ -0,0 +1,51 @@
+//! This file defines the source of random numbers used by the simulation.
+//! Every random decision goes through the Random trait, so that a simulation started with the same
+//! seed and given the same inputs will always produce exactly the same results.
+
+/// The Random trait defines a common interface for random number generators
+/// Any struct that implements this trait can be used to drive the simulation
+pub trait Random {
+    /// Returns a random float between 0 (inclusive) and 1 (exclusive)
+    fn rand(&mut self) -> f64;
+}
+
+/// A small and fast pseudo-random number generator (xorshift64*) that can be seeded
+/// It's not suitable for cryptography, but it's more than good enough for moving sand around
+#[derive(Clone, Debug)]
+pub struct SeededRandom {
+    /// The internal state of the generator, which must never be zero
+    state: u64,
+}
+
+impl SeededRandom {
+    /// Creates a new generator from the given seed
+    /// Any seed is allowed (including 0), because it's scrambled before being used as the state
+    pub fn new(seed: u64) -> SeededRandom {
+        // Run the seed through one round of splitmix64 so that similar seeds give very different sequences
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+
+        SeededRandom {
+            state: if z == 0 { 0x9E37_79B9_7F4A_7C15 } else { z },
+        }
+    }
+
+    /// Advances the generator and returns the next 64 bits
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+impl Random for SeededRandom {
+    fn rand(&mut self) -> f64 {
+        // Use the top 53 bits to make a float in the range [0, 1)
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,356 @@
+//! This file reads the rules for the cellular automaton simulator (CellularSim) from text, so that
+//! new rules can be tried out without recompiling anything.
+//!
+//! Each rule starts with the word `rule`, optionally followed by the probability (from 0 to 1) that
+//! it's applied when it matches.  The next two lines describe the 2x2 block of cells that the rule
+//! matches on the left side of the `=>`, and what the block turns into on the right side:
+//!
+//! ```text
+//! # Sand falls into the empty space below it
+//! rule 1.0
+//! sand   *  =>  2  =
+//! empty  *  =>  0  =
+//! ```
+//!
+//! The cells on the left side can be:
+//! - the name of a cell type (eg. `sand` or `empty`), which matches only that type
+//! - `any-liquid`, `any-gas`, `any-granular` or `any-static`, which match any type that moves that way
+//! - `flammable`, `explosive` or `dissolvable`, which match any type with that property
+//! - `<2` or `>2`, which match any type that's lighter or denser than the cell at that position
+//! - `!pattern`, which matches anything the pattern doesn't (eg. `!empty`)
+//! - `{pattern,pattern}`, which matches anything one of the patterns matches (eg. `{sand,water}`)
+//! - `pattern&pattern`, which matches only what both patterns match (eg. `any-liquid&>2`)
+//! - `*`, which matches anything
+//!
+//! A cell on the left side can also be given a name by writing the name and a colon in front of it
+//! (eg. `x:any-liquid`).  The name can then be used elsewhere on the left side to match the same type
+//! as that cell, or on the right side to move that cell, whatever it turned out to be.
+//!
+//! The cells on the right side can be:
+//! - the name of a cell type, which changes the cell into that type (keeping its temperature)
+//! - a position from 0 to 3 (top left, top right, bottom left, bottom right), or the name given to a
+//!   cell on the left side, which moves that cell into this one
+//! - `=`, which leaves the cell as it was
+//!
+//! For example, this rule lets a liquid that's on top of a lighter liquid sink below it, by swapping them:
+//!
+//! ```text
+//! rule 0.5
+//! top:any-liquid&>2   *  =>  bottom  =
+//! bottom:any-liquid   *  =>  top     =
+//! ```
+//!
+//! Blank lines are ignored, and anything after a `#` is a comment.  The rules are tried in the order
+//! they're written, and each cell can only be changed by one rule in each tick.
+
+use std::fmt;
+
+use crate::cells::{ CellType, Movement };
+use crate::simulator::{ CellRule, MatchCell, ModifyCell, NeighbourhoodPattern, NeighbourhoodModifier };
+
+/// An error found while reading rules, along with the line it was found on
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleError {
+    /// The line number the problem was found on (starting from 1)
+    pub line: usize,
+    /// A description of the problem
+    pub message: String,
+}
+
+impl RuleError {
+    /// Creates a new error for the given line
+    fn new(line: usize, message: String) -> RuleError {
+        RuleError {
+            line,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for RuleError { }
+
+/// Reads all the rules in the given text, or returns an error describing the first problem found
+pub(crate) fn parse_rules(text: &str) -> Result<Vec<CellRule>, RuleError> {
+    let mut rules = vec![];
+
+    // Number the lines (starting from 1), and skip over comments and blank lines
+    let mut lines = text.lines()
+        .enumerate()
+        .map(|(number, line)| (number + 1, line.split('#').next().unwrap_or("").trim()))
+        .filter(|(_, line)| !line.is_empty());
+
+    while let Some((number, line)) = lines.next() {
+        let probability = parse_header(number, line)?;
+
+        // The header must be followed by the top and bottom rows of the block
+        let mut row = || lines.next().ok_or_else(|| RuleError::new(number, "the rule is missing its two rows of cells".to_string()));
+        let (top_number, top) = row()?;
+        let (bottom_number, bottom) = row()?;
+        let (top_if, top_then) = split_row(top_number, top)?;
+        let (bottom_if, bottom_then) = split_row(bottom_number, bottom)?;
+
+        // The words for each of the 4 positions in the block, along with the line each one is on
+        let if_words = [(top_number, top_if[0]), (top_number, top_if[1]), (bottom_number, bottom_if[0]), (bottom_number, bottom_if[1])];
+        let then_words = [(top_number, top_then[0]), (top_number, top_then[1]), (bottom_number, bottom_then[0]), (bottom_number, bottom_then[1])];
+
+        // Find the names given to cells on the left side first, so that they can be used anywhere in the rule
+        let mut names = vec![];
+        for (position, (number, word)) in if_words.iter().enumerate() {
+            if let Some((name, _)) = word.split_once(':') {
+                if !is_valid_name(name) {
+                    return Err(RuleError::new(*number, format!("\"{}\" can't be used as a name (names must be a single word that isn't a cell type or pattern)", name)));
+                }
+                if names.iter().any(|(other, _)| *other == name) {
+                    return Err(RuleError::new(*number, format!("the name \"{}\" is given to more than one cell", name)));
+                }
+                names.push((name, position));
+            }
+        }
+
+        let if_nb: NeighbourhoodPattern = [
+            parse_named_match(if_words[0], &names)?,
+            parse_named_match(if_words[1], &names)?,
+            parse_named_match(if_words[2], &names)?,
+            parse_named_match(if_words[3], &names)?,
+        ];
+        let then_nb: NeighbourhoodModifier = [
+            parse_modify(then_words[0].0, then_words[0].1, &names)?,
+            parse_modify(then_words[1].0, then_words[1].1, &names)?,
+            parse_modify(then_words[2].0, then_words[2].1, &names)?,
+            parse_modify(then_words[3].0, then_words[3].1, &names)?,
+        ];
+        rules.push(CellRule::new(probability, if_nb, then_nb));
+    }
+
+    if rules.is_empty() {
+        return Err(RuleError::new(text.lines().count().max(1), "no rules were found".to_string()));
+    }
+    Ok(rules)
+}
+
+/// Reads the line that starts a rule (eg. "rule 0.5") and returns the rule's probability
+fn parse_header(number: usize, line: &str) -> Result<f64, RuleError> {
+    let mut words = line.split_whitespace();
+    if words.next() != Some("rule") {
+        return Err(RuleError::new(number, format!("expected the start of a rule (eg. \"rule 0.5\") but found \"{}\"", line)));
+    }
+
+    let probability = match words.next() {
+        Some(word) => match word.parse::<f64>() {
+            Ok(probability) if (0.0..=1.0).contains(&probability) => probability,
+            _ => return Err(RuleError::new(number, format!("the probability \"{}\" must be a number from 0 to 1", word))),
+        },
+        None => 1.0,
+    };
+
+    if let Some(word) = words.next() {
+        return Err(RuleError::new(number, format!("unexpected \"{}\" after the probability", word)));
+    }
+    Ok(probability)
+}
+
+/// Splits one row of a rule (eg. "sand * => 2 =") into the two cells to match and the two changes to make
+fn split_row(number: usize, line: &str) -> Result<([&str; 2], [&str; 2]), RuleError> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.len() != 5 || words[2] != "=>" {
+        return Err(RuleError::new(number, format!("expected two cells, then \"=>\", then two cells, but found \"{}\"", line)));
+    }
+    Ok(([words[0], words[1]], [words[3], words[4]]))
+}
+
+/// Reads a cell on the left side of a rule, skipping over the name given to it (if it has one)
+fn parse_named_match((number, word): (usize, &str), names: &[(&str, usize)]) -> Result<MatchCell, RuleError> {
+    let pattern = word.split_once(':').map_or(word, |(_, pattern)| pattern);
+    parse_match(number, pattern, names)
+}
+
+/// Reads a pattern on the left side of a rule, which is matched against the cells in the space
+/// The names given to cells in the rule (and their positions) are needed to match the same type as a named cell
+fn parse_match(number: usize, word: &str, names: &[(&str, usize)]) -> Result<MatchCell, RuleError> {
+    // The & sign has the lowest priority, so "!sand&any-granular" means "(not sand) and (any granular)"
+    let parts = split_outside_braces(word, '&');
+    if parts.len() > 1 {
+        let patterns = parts.iter().map(|part| parse_match(number, part, names)).collect::<Result<_, _>>()?;
+        return Ok(MatchCell::AllOf(patterns));
+    }
+
+    if let Some(rest) = word.strip_prefix('!') {
+        return Ok(MatchCell::Not(Box::new(parse_match(number, rest, names)?)));
+    }
+    if let Some(rest) = word.strip_prefix('{') {
+        let inside = rest.strip_suffix('}')
+            .ok_or_else(|| RuleError::new(number, format!("the set \"{}\" is missing its closing \"}}\"", word)))?;
+        let patterns = split_outside_braces(inside, ',').iter()
+            .map(|part| parse_match(number, part, names))
+            .collect::<Result<_, _>>()?;
+        return Ok(MatchCell::AnyOf(patterns));
+    }
+    if let Some(rest) = word.strip_prefix('<') {
+        return parse_position(number, rest).map(MatchCell::Lighter);
+    }
+    if let Some(rest) = word.strip_prefix('>') {
+        return parse_position(number, rest).map(MatchCell::Denser);
+    }
+    if let Some((_, position)) = names.iter().find(|(name, _)| *name == word) {
+        return Ok(MatchCell::SameAs(*position));
+    }
+
+    let pattern = match word.to_ascii_lowercase().as_str() {
+        "*" => MatchCell::Any,
+        "any-liquid" => MatchCell::Class(Movement::Liquid),
+        "any-gas" => MatchCell::Class(Movement::Gas),
+        "any-granular" => MatchCell::Class(Movement::Granular),
+        "any-static" => MatchCell::Class(Movement::Static),
+        "flammable" => MatchCell::Flammable,
+        "explosive" => MatchCell::Explosive,
+        "dissolvable" => MatchCell::Dissolvable,
+        _ => MatchCell::Exact(parse_cell_type(number, word)?),
+    };
+    Ok(pattern)
+}
+
+/// Reads a cell on the right side of a rule, which says how the cell in that position is changed
+/// The names given to cells in the rule (and their positions) are needed to move a named cell
+fn parse_modify(number: usize, word: &str, names: &[(&str, usize)]) -> Result<ModifyCell, RuleError> {
+    if let Some((_, position)) = names.iter().find(|(name, _)| *name == word) {
+        return Ok(ModifyCell::Move(*position));
+    }
+
+    match word {
+        "=" => Ok(ModifyCell::Same),
+        _ if word.parse::<i64>().is_ok() => parse_position(number, word).map(ModifyCell::Move),
+        _ => parse_cell_type(number, word).map(ModifyCell::Type),
+    }
+}
+
+/// Reads a position in the block of cells, from 0 (top left) to 3 (bottom right)
+fn parse_position(number: usize, word: &str) -> Result<usize, RuleError> {
+    match word {
+        "0" | "1" | "2" | "3" => Ok(word.parse().unwrap()),
+        _ => Err(RuleError::new(number, format!("the position \"{}\" must be 0, 1, 2 or 3", word))),
+    }
+}
+
+/// Reads the name of a cell type
+fn parse_cell_type(number: usize, word: &str) -> Result<CellType, RuleError> {
+    CellType::from_name(word).ok_or_else(|| RuleError::new(number, format!("unknown cell type or name \"{}\"", word)))
+}
+
+/// Splits the text at each of the given separators, except for the ones inside of braces
+/// This lets sets be nested inside of other patterns (eg. "{sand,water}&!<2" is split into "{sand,water}" and "!<2")
+fn split_outside_braces(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if c == '{' {
+            depth += 1;
+        } else if c == '}' {
+            depth -= 1;
+        } else if c == separator && depth == 0 {
+            parts.push(&text[start..i]);
+            start = i + 1;
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Checks if a word can be given to a cell as its name
+/// Names must be a single word (letters, digits, _ and -) that isn't a number, a cell type or a pattern
+fn is_valid_name(name: &str) -> bool {
+    let patterns = ["any-liquid", "any-gas", "any-granular", "any-static", "flammable", "explosive", "dissolvable"];
+    !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && name.parse::<i64>().is_err()
+        && CellType::from_name(name).is_none()
+        && !patterns.iter().any(|pattern| pattern.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::space::Space;
+    use crate::random::Random;
+    use crate::forces::ForceField;
+    use crate::simulator::{ Simulator, CellularSim };
+
+    /// A random number generator that always gives 0, so every rule that matches passes its probability check
+    struct AlwaysZero;
+
+    impl Random for AlwaysZero {
+        fn rand(&mut self) -> f64 {
+            0.0
+        }
+    }
+
+    /// Runs the given rules for one tick on a 2x2 space holding the given cells (in the order of the positions
+    /// in a rule: top left, top right, bottom left, bottom right), and returns the cells afterwards
+    fn tick_once(text: &str, cells: [CellType; 4]) -> [CellType; 4] {
+        let mut sim = CellularSim::with_rules(text).unwrap();
+        let mut space = Space::new(2, 2);
+        let mut rng = AlwaysZero;
+        for (i, cell_type) in cells.iter().enumerate() {
+            space.get_cell_at(i).init(*cell_type, &mut rng);
+        }
+
+        // The blocks are only lined up with the edge of the space on even generations, so skip the first one
+        space.increment_generation();
+        sim.tick(&mut space, &ForceField::new(2, 2), &mut rng);
+        [space.get_cell_type_at(0), space.get_cell_type_at(1), space.get_cell_type_at(2), space.get_cell_type_at(3)]
+    }
+
+    #[test]
+    fn valid_rule() {
+        let text = "# Sand falls into the empty space below it\n\nrule\nsand  *  =>  empty  =\nempty *  =>  sand   =\n";
+        assert_eq!(parse_rules(text).map(|rules| rules.len()), Ok(1));
+        assert_eq!(tick_once(text, [CellType::Sand, CellType::Rock, CellType::Empty, CellType::Rock]),
+            [CellType::Empty, CellType::Rock, CellType::Sand, CellType::Rock]);
+    }
+
+    #[test]
+    fn unknown_material() {
+        let err = parse_rules("# A comment\n\nrule 0.5\ncheese  *  =>  =  =\n*       *  =>  =  =\n").err().unwrap();
+        assert_eq!(err, RuleError::new(4, "unknown cell type or name \"cheese\"".to_string()));
+    }
+
+    #[test]
+    fn malformed_patterns() {
+        let err = parse_rules("rule\n{sand,water  *  =>  =  =\n*  *  =>  =  =\n").err().unwrap();
+        assert_eq!(err, RuleError::new(2, "the set \"{sand,water\" is missing its closing \"}\"".to_string()));
+
+        let err = parse_rules("rule\nsand  *  =>  =  =\nany-liquid&>5  *  =>  =  =\n").err().unwrap();
+        assert_eq!(err, RuleError::new(3, "the position \"5\" must be 0, 1, 2 or 3".to_string()));
+
+        let err = parse_rules("rule\nsand  *  ->  =  =\n*  *  =>  =  =\n").err().unwrap();
+        assert_eq!(err, RuleError::new(2, "expected two cells, then \"=>\", then two cells, but found \"sand  *  ->  =  =\"".to_string()));
+
+        let err = parse_rules("rule 2\nsand  *  =>  =  =\n*  *  =>  =  =\n").err().unwrap();
+        assert_eq!(err, RuleError::new(1, "the probability \"2\" must be a number from 0 to 1".to_string()));
+
+        let err = parse_rules("rule\nsand  *  =>  =  =\n").err().unwrap();
+        assert_eq!(err, RuleError::new(1, "the rule is missing its two rows of cells".to_string()));
+    }
+
+    #[test]
+    fn errors_give_the_line_number() {
+        // The mistake is in the second rule, after some comments and blank lines
+        let text = "# Two rules\nrule\nsand  *  =>  empty  =\nempty *  =>  sand   =\n\n# The second one has a mistake\nrule 0.5\nwater  *  =>  =  =\n*  *  =>  =  wtaer\n";
+        let err = parse_rules(text).err().unwrap();
+        assert_eq!(err.line, 9);
+        assert_eq!(err.to_string(), "line 9: unknown cell type or name \"wtaer\"");
+    }
+
+    #[test]
+    fn example_rules_sink_heavier_liquids() {
+        // Water (heavier) on top of oil (lighter), with a wall beside them
+        assert_eq!(tick_once(include_str!("../www/example-rules.txt"), [CellType::Water, CellType::Rock, CellType::Oil, CellType::Rock]),
+            [CellType::Oil, CellType::Rock, CellType::Water, CellType::Rock]);
+    }
+}
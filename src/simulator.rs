@@ -2,19 +2,88 @@
 //! It defines how different cell types behave and interact with each other.
 
 use std::convert::TryInto;
+use std::collections::HashMap;
 
 // Import utility functions and macros
 #[allow(unused_imports)]
-use crate::{ log, rand, debug_print };
+use crate::{ log, debug_print };
 // Import our game modules
 use crate::space::Space;
-use crate::cells::{ Cell, CellType, CellTypeProperties };
+use crate::random::Random;
+use crate::forces::{ ForceField, rotate_direction };
+use crate::rules::{ RuleError, parse_rules };
+use crate::cells::{ Cell, CellType, CellTypeProperties, Movement, Reactant };
+use crate::materials::MaterialRegistry;
+
+/// The fraction of the temperature difference between two neighbouring cells that can be
+/// exchanged in one tick.  Each cell exchanges with up to 4 neighbours, so this must stay at or
+/// below 0.25 for the exchange to remain stable (ie. to never overshoot the equilibrium)
+const HEAT_TRANSFER_RATE: f32 = 0.25;
+/// The fraction of the difference from the ambient temperature that empty space loses each tick
+const AMBIENT_COOLING_RATE: f32 = 0.01;
+/// The most cells to search through when looking for somewhere to push a displaced cell
+const MAX_DISPLACE_SEARCH: usize = 64;
+/// The fraction of a fire's temperature that it releases into each of its neighbours every tick
+const FIRE_HEAT_RATE: f32 = 0.1;
+/// How far away (in cells) an explosion affects other cells
+const EXPLOSION_RADIUS: i32 = 6;
+/// How close (in cells) to an explosion everything is destroyed rather than pushed away
+const EXPLOSION_CORE_RADIUS: i32 = 2;
+/// How hot an explosion makes the cells at its center (this falls off towards the edge of the blast)
+const EXPLOSION_HEAT: f32 = 800.0;
+/// The fastest a cell can move (in cells per tick), which also limits how far a cell is traced each tick
+const MAX_VELOCITY: f32 = 8.0;
+/// The fraction of their sideways speed that liquids keep each tick
+const LIQUID_FRICTION: f32 = 0.9;
+/// The fraction of their sideways speed that granular materials keep each tick
+const GRANULAR_FRICTION: f32 = 0.5;
+/// The fraction of a liquid's falling speed that gets turned into sideways speed when it lands
+const LIQUID_SPLASH: f32 = 0.5;
+/// The sideways speed that a liquid picks up when it flows sideways, so that it keeps spreading out
+const LIQUID_SPREAD: f32 = 1.0;
+/// How much the wind speeds up liquids and granular materials each tick (this is divided by their density)
+const WIND_EFFECT: f32 = 0.1;
+/// The chance each tick that a seed resting on sand and touching water sprouts into a plant
+const SEED_SPROUT_CHANCE: f64 = 0.02;
+/// The chance each tick that a plant drinks a cell of water that it's touching, and grows by one cell
+const PLANT_GROWTH_CHANCE: f64 = 0.05;
+/// The chance that a plant grows a new branch out of its side instead of growing taller
+const PLANT_BRANCH_CHANCE: f64 = 0.1;
+/// The tallest a plant can grow (in cells), counting up from the cell that's drinking the water
+const MAX_PLANT_HEIGHT: usize = 40;
+/// How much a spark heats up the cell carrying it, each tick
+const SPARK_HEAT: f32 = 10.0;
+/// The chance each tick that a tap drips out a new cell of its material
+const TAP_RATE: f64 = 0.1;
 
 /// The Simulator trait defines a common interface for different simulation approaches
 /// Any struct that implements this trait can be used as the simulation engine
 pub trait Simulator {
     /// The tick method advances the simulation by one time step
-    fn tick(&mut self, space: &mut Space);
+    /// The given forces (gravity and wind) decide which way the cells are pushed, and all random
+    /// decisions must be made using the given random number generator, so that the simulation is
+    /// reproducible from its seed
+    fn tick(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random);
+}
+
+/// A function that creates a new simulator
+pub type NewSimulator = fn() -> Box<dyn Simulator>;
+
+/// All the simulators that can be chosen from, by name, along with a function to create each one
+/// The first one is used by default
+pub static SIMULATORS: [(&str, NewSimulator); 2] = [
+    ("Swapping", new_swapping_sim),
+    ("Cellular", new_cellular_sim),
+];
+
+/// Creates a new SwappingSim (for the SIMULATORS list)
+fn new_swapping_sim() -> Box<dyn Simulator> {
+    Box::new(SwappingSim { })
+}
+
+/// Creates a new CellularSim (for the SIMULATORS list)
+fn new_cellular_sim() -> Box<dyn Simulator> {
+    Box::new(CellularSim::new())
 }
 
 /// SwappingSim is our main simulation implementation
@@ -23,20 +92,24 @@ pub struct SwappingSim;
 
 /// Implement the Simulator trait for SwappingSim
 impl Simulator for SwappingSim {
-    fn tick(&mut self, space: &mut Space) {
+    fn tick(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random) {
         // Delegate to our main simulation method
-        self.advance(space);
+        self.advance(space, forces, rng);
     }
 }
 
 impl SwappingSim {
     /// The main simulation method that advances the simulation by one time step
-    pub fn advance(&mut self, space: &mut Space) {
+    pub fn advance(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random) {
         // Increment the generation counter (used to track which cells have been updated)
         space.increment_generation();
 
-        // Process the grid from bottom to top (important for gravity simulation)
-        for y in (0..(space.get_height() as i32)).rev() {
+        // Process the grid from bottom to top (important for gravity simulation), or from top to
+        // bottom if gravity has been turned upside down, so that falling cells are only moved once
+        let height = space.get_height() as i32;
+        let upwards = forces.gravity.1 < 0.0;
+        for row in 0..height {
+            let y = if upwards { row } else { height - row - 1 };
             for x in 0..(space.get_width() as i32) {
                 // Alternate the direction we scan each row to reduce directional bias
                 // Sweeping left to right to left removes the bias in the x direction, but not the y direction
@@ -50,91 +123,156 @@ impl SwappingSim {
                 // This prevents a cell from being updated multiple times in one tick
                 if space.cell_needs_updating(i) {
                     // Apply the simulation rules to this cell
-                    self.simulate_cell(space, x, y);
+                    self.simulate_cell(space, forces, rng, x, y);
                     // Mark this cell as updated for this generation
                     space.update_cell_generation(i);
                 }
             }
         }
+
+        // Once everything has moved, let neighbouring cells exchange heat
+        self.transfer_heat(space, forces);
+    }
+
+    /// Exchanges heat between every pair of neighbouring cells
+    /// Each pair is only visited once (by looking right and down from each cell), and whatever heat
+    /// one cell loses, the other gains, so the total heat is conserved by the exchange.  The rate is
+    /// weighted by the temp_coefficient of both cells, so a good conductor next to a poor one exchanges
+    /// heat slowly, much like two resistors in series
+    fn transfer_heat(&mut self, space: &mut Space, forces: &ForceField) {
+        // This visits every cell, so look up the registry of materials just once rather than for every cell
+        let materials = MaterialRegistry::get();
+        for y in 0..(space.get_height() as i32) {
+            for x in 0..(space.get_width() as i32) {
+                let i = space.get_index(x as u32, y as u32);
+
+                for (nx, ny) in [(x + 1, y), (x, y + 1)] {
+                    if let Some(ni) = space.get_index_checked(nx, ny) {
+                        let (temp, coefficient) = {
+                            let cell = space.get_cell_at(i);
+                            (cell.temp, materials.get_properties(cell.cell_type).temp_coefficient)
+                        };
+                        let (ntemp, ncoefficient) = {
+                            let cell = space.get_cell_at(ni);
+                            (cell.temp, materials.get_properties(cell.cell_type).temp_coefficient)
+                        };
+
+                        // Combine the two coefficients like conductors in series, and then scale it so
+                        // that even the best conductors never exchange more than the maximum rate
+                        let conductance = (coefficient * ncoefficient) / (coefficient + ncoefficient);
+                        let change = (temp - ntemp) * HEAT_TRANSFER_RATE * conductance / (1.0 + conductance);
+
+                        space.get_cell_at(i).temp -= change;
+                        space.get_cell_at(ni).temp += change;
+                    }
+                }
+
+                // Empty space slowly returns to the ambient temperature, which lets heat leave the system
+                // (or come into it, when the air is colder than the cells, which is how water freezes)
+                let cell = space.get_cell_at(i);
+                if cell.cell_type == CellType::Empty {
+                    cell.temp += (forces.ambient_temp - cell.temp) * AMBIENT_COOLING_RATE;
+                }
+            }
+        }
     }
 
     /// Simulates behavior for a single cell based on its type
-    pub fn simulate_cell(&mut self, space: &mut Space, x: i32, y: i32) {
+    pub fn simulate_cell(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32) {
         // Get the index and cell at the current position
         let i = space.get_index(x as u32, y as u32);
         let cell = space.get_cell_at(i);
 
+        // Flammable cells catch fire once they've been heated past their ignition temperature
+        let props = cell.get_properties();
+        if props.flammable && cell.temp >= props.ignition_temp {
+            // Explosive cells detonate instead of just burning
+            if props.explosive {
+                self.explode(space, rng, x, y);
+                return;
+            }
+            self.ignite(space, rng, x, y);
+        }
+
+        // Cells that get hot or cold enough change state (eg. water boiling into steam)
+        let cell = space.get_cell_at(i);
+        if let Some(cell_type) = cell.get_properties().phase_change_at(cell.temp) {
+            cell.cell_type = cell_type;
+            cell.start_lifetime(rng);
+        }
+
+        // Cells that don't last forever (eg. smoke) fade away into empty space once their time is up
+        if cell.lifetime > 0 {
+            cell.lifetime -= 1;
+            if cell.lifetime == 0 {
+                cell.cell_type = CellType::Empty;
+                return;
+            }
+        }
+
+        // Sparks travel along conductive cells (eg. metal wires), and then the cell rests for a while
+        if cell.charge > 0 {
+            self.conduct(space, x, y);
+        }
+
+        // Cells react with the cells they're touching (eg. water cooling lava into rock)
+        let cell = space.get_cell_at(i);
+        if !cell.get_properties().reactions.is_empty() && self.react(space, rng, x, y) {
+            return;
+        }
+
         // Apply different behavior based on the cell type
+        let cell = space.get_cell_at(i);
         match cell.cell_type {
-            // Empty space doesn't do anything
-            CellType::Empty => { },
-
-            // Fixed solid materials (don't move)
-            CellType::Wood |
-            CellType::Rock => {
-                // These don't move, so no action needed
-            },
+            // Lava is hot and can ignite things (by heating them up through transfer_heat())
+            CellType::Lava => {
+                // Lava cools down over time (and turns into rock once it's cool enough)
+                cell.temp -= rng.rand() as f32 * 5.0;
 
-            // Granular materials (like sand - fall down and pile up)
-            CellType::Sand |
-            CellType::Gunpowder => {
-                self.move_granular(space, x, y);
+                // Lava also flows like a liquid
+                self.move_liquid(space, forces, rng, x, y);
             },
 
-            // Liquids (flow down and spread out)
-            CellType::Oil |
-            CellType::Water => {
-                self.move_liquid(space, x, y);
+            // Seeds fall like sand, until they land somewhere they can sprout
+            CellType::Seed => {
+                if !self.sprout(space, forces, rng, x, y) {
+                    self.move_granular(space, forces, rng, x, y);
+                }
             },
 
-            // Gases (rise and spread)
-            CellType::Propane => {
-                self.move_gas(space, x, y);
+            // Plants grow by drinking the water they're touching
+            CellType::Plant => {
+                self.grow(space, forces, rng, x, y);
             },
 
-            // Acid dissolves other materials it touches
-            CellType::Acid => {
-                let mut expend = false;
-                // Check each neighboring cell
-                self.foreach_neighbour(space, x, y, |cell, props| {
-                    // If the neighbor can be dissolved and we pass a random check
-                    if props.dissolvable && rand() < 0.005 {
-                        // Dissolve the neighbor (turn it into empty space)
-                        cell.init(CellType::Empty);
-                        // Mark that the acid was used up
-                        expend = true;
+            // Batteries keep sparking the metal they're touching (as soon as it's ready for another spark)
+            CellType::Battery => {
+                self.foreach_neighbour_index(space, x, y, |space, ni| {
+                    if space.get_cell_at(ni).spark() {
+                        // The new spark doesn't move on until the next tick
+                        space.update_cell_generation(ni);
                     }
                 });
-
-                // If the acid was used to dissolve something, it gets used up
-                if expend {
-                    let cell = space.get_cell_at(i);
-                    cell.init(CellType::Empty);
-                } else {
-                    // Otherwise, it behaves like a liquid
-                    self.move_liquid(space, x, y);
-                }
             },
 
-            // Lava is hot and can ignite things
-            CellType::Lava => {
-                // Lava cools down over time
-                cell.temp -= rand() as f32 * 5.0;
-                // If it cools enough, it turns into rock
-                if cell.temp < 10.0 {
-                    cell.cell_type = CellType::Rock;
-                }
+            // Clones and taps make copies of the material they're emitting
+            CellType::Clone | CellType::Tap => {
+                self.emit(space, forces, rng, x, y);
+            },
 
-                // Lava can ignite neighboring flammable materials
-                self.ignite_neighbours(cell.temp, space, x, y);
-                // Lava also flows like a liquid
-                self.move_liquid(space, x, y);
+            // Drains delete anything that moves into them (but not solid things, like the walls around them)
+            CellType::Drain => {
+                self.foreach_neighbour(space, x, y, |cell, props| {
+                    if props.movement != Movement::Static {
+                        *cell = Cell::empty();
+                    }
+                });
             },
 
             // Fire burns and spreads
             CellType::Fire => {
                 // Fire loses heat over time
-                cell.temp -= rand() as f32 * 5.0;
+                cell.temp -= rng.rand() as f32 * 5.0;
                 // If it cools enough, it goes out
                 if cell.temp < 10.0 {
                     cell.cell_type = CellType::Empty;
@@ -142,67 +280,337 @@ impl SwappingSim {
                 }
 
                 // Hot fire has a chance to spawn new fire cells nearby
-                if cell.temp > 300.0 && rand() < 0.4 {
+                if cell.temp > 300.0 && rng.rand() < 0.4 {
                     // Reduce temperature when spreading
                     cell.temp = cell.temp * 3.0 / 5.0;
                     let cell = *cell;
                     // Create a new fire cell in a nearby position
-                    self.spawn_new(space, x, y, cell);
+                    self.spawn_new(space, rng, x, y, cell);
                 }
 
-                // Get the updated cell and try to ignite neighbors
-                let cell = space.get_cell_at(i);
-                self.ignite_neighbours(cell.temp, space, x, y);
+                // Burning releases heat into the neighbouring cells, which is what spreads the fire
+                let temp = space.get_cell_at(i).temp;
+                self.heat_neighbours(space, x, y, temp * FIRE_HEAT_RATE);
 
                 // Fire also has a chance to rise like a gas
-                if rand() < 0.25 {
-                    self.move_gas(space, x, y);
+                if rng.rand() < 0.25 {
+                    self.move_gas(space, forces, rng, x, y);
+                }
+            },
+
+            // Everything else just moves the way its material does
+            _ => match cell.get_properties().movement {
+                // Fixed solid materials (and empty space) don't move
+                Movement::Static => { },
+                // Granular materials (like sand) fall down and pile up
+                Movement::Granular => self.move_granular(space, forces, rng, x, y),
+                // Liquids flow down and spread out
+                Movement::Liquid => self.move_liquid(space, forces, rng, x, y),
+                // Gases rise and spread
+                Movement::Gas => self.move_gas(space, forces, rng, x, y),
+            },
+        }
+    }
+
+    /// Checks the reactions of the cell at the given position against each of its neighbours (the reaction
+    /// table in materials.txt), and applies the first one that happens
+    /// Returns true if the cell reacted (so it won't do anything else this tick)
+    fn react(&mut self, space: &mut Space, rng: &mut dyn Random, x: i32, y: i32) -> bool {
+        let i = space.get_index(x as u32, y as u32);
+        let cell = *space.get_cell_at(i);
+        let reactions = &cell.get_properties().reactions;
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let ni = match space.get_index_checked(x + dx, y + dy) {
+                    Some(ni) if ni != i => ni,
+                    _ => continue,
+                };
+
+                let other = space.get_cell_type_at(ni);
+                for reaction in reactions.iter().filter(|reaction| reaction.other.matches(other)) {
+                    let (low, high) = reaction.temp_range;
+                    if cell.temp >= low && cell.temp <= high && rng.rand() < reaction.probability {
+                        self.change_cell(space, rng, x + dx, y + dy, reaction.other_becomes, reaction.heat);
+                        self.change_cell(space, rng, x, y, reaction.becomes, reaction.heat);
+                        return true;
+                    }
                 }
+            }
+        }
+        false
+    }
+
+    /// Turns the cell at the given position into the product of a reaction (if it changes), and adds the
+    /// heat given off by the reaction.  New cells start at the normal temperature for their type, except
+    /// that a cell turned into fire catches fire (so it's at least as hot as it was, or explodes if it's explosive)
+    fn change_cell(&mut self, space: &mut Space, rng: &mut dyn Random, x: i32, y: i32, product: Option<CellType>, heat: f32) {
+        let i = space.get_index(x as u32, y as u32);
+        match product {
+            Some(CellType::Fire) if space.get_cell_at(i).get_properties().explosive => {
+                self.explode(space, rng, x, y);
+                return;
             },
+            Some(CellType::Fire) => self.ignite(space, rng, x, y),
+            Some(cell_type) => space.get_cell_at(i).init(cell_type, rng),
+            None => { },
+        }
+        space.get_cell_at(i).temp += heat;
+        space.update_cell_generation(i);
+    }
+
+    /// Counts down the charge of the cell at the given position, and if it's carrying a spark, passes the spark
+    /// on to the conductive cells it's touching and heats up.  That heat spreads to the cells around the wire, so
+    /// sparks can set fire to flammable cells, but only once they're hot enough to catch
+    /// The cells that are sparked are marked as updated, so that the spark moves exactly one cell each tick, no
+    /// matter which order the cells are visited in
+    fn conduct(&mut self, space: &mut Space, x: i32, y: i32) {
+        let i = space.get_index(x as u32, y as u32);
+        let cell = space.get_cell_at(i);
+        let sparking = cell.is_sparking();
+        cell.charge -= 1;
+        if !sparking {
+            return;
+        }
+        cell.temp += SPARK_HEAT;
+
+        self.foreach_neighbour_index(space, x, y, |space, ni| {
+            if space.get_cell_at(ni).spark() {
+                space.update_cell_generation(ni);
+            }
+        });
+    }
+
+    /// Emits copies of the material that the emitter (a clone or a tap) at the given position is set to
+    /// If it hasn't been set yet, it picks up the first material that touches it.  A clone fills all the empty
+    /// space around it every tick, while a tap only drips out a cell every so often, into the space below it
+    fn emit(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32) {
+        let i = space.get_index(x as u32, y as u32);
+        if space.get_cell_at(i).emits == CellType::Empty {
+            let mut touching = CellType::Empty;
+            self.foreach_neighbour(space, x, y, |cell, _| {
+                if touching == CellType::Empty && cell.cell_type.can_be_emitted() {
+                    touching = cell.cell_type;
+                }
+            });
+            space.get_cell_at(i).emits = touching;
+        }
+
+        let cell = *space.get_cell_at(i);
+        if cell.emits == CellType::Empty {
+            return;
+        }
+
+        let targets = if cell.cell_type == CellType::Tap {
+            if rng.rand() >= TAP_RATE {
+                return;
+            }
+            // Taps drip downwards (or down the screen, if there's no gravity)
+            let (down_x, down_y) = forces.gravity_direction().unwrap_or((0, 1));
+            vec![(x + down_x, y + down_y)]
+        } else {
+            (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (x + dx, y + dy))).collect()
+        };
+
+        for (tx, ty) in targets {
+            if let Some(ti) = space.get_index_checked(tx, ty) {
+                if space.get_cell_type_at(ti) == CellType::Empty {
+                    space.get_cell_at(ti).init(cell.emits, rng);
+                    // The new cell doesn't move until the next tick
+                    space.update_cell_generation(ti);
+                }
+            }
         }
     }
 
     /// Handles movement for granular materials like sand
-    fn move_granular(&mut self, space: &mut Space, x: i32, y: i32) {
+    fn move_granular(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32) {
+        // Fast moving cells travel along their velocity first, and only fall back to the
+        // normal rules below if they couldn't move (eg. they're resting on something)
+        if self.follow_velocity(space, forces, rng, x, y, false) {
+            return;
+        }
+
+        // Without gravity, there's no way to fall
+        let (down_x, down_y) = match forces.gravity_direction() {
+            Some(down) => down,
+            None => return,
+        };
+
         let i = space.get_index(x as u32, y as u32);
         // Randomly choose left or right for the diagonal movement
-        let d = if rand() > 0.5 { 1 } else { -1 };
+        let d = if rng.rand() > 0.5 { 1 } else { -1 };
+        // "Below" is whichever way gravity is pulling, so turn it to get the diagonal
+        let (diagonal_x, diagonal_y) = rotate_direction((down_x, down_y), d);
 
         // Check these positions in order: directly below, diagonally below
         let check = vec!(
-            (x, y + 1),           // Directly below
-            (x + d, y + 1),       // Diagonally below
+            (x + down_x, y + down_y),           // Directly below
+            (x + diagonal_x, y + diagonal_y),   // Diagonally below
         );
 
         // Try to swap with cells in these positions if possible
-        self.check_swap_from_list(space, i, check, SwappingSim::check_density);
+        self.check_swap_from_list(space, forces, rng, i, check, SwappingSim::check_density);
     }
 
     /// Handles movement for liquids like water and oil
-    fn move_liquid(&mut self, space: &mut Space, x: i32, y: i32) {
+    fn move_liquid(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32) {
+        // Fast moving cells travel along their velocity first, and only fall back to the
+        // normal rules below if they couldn't move (eg. they're resting on something)
+        if self.follow_velocity(space, forces, rng, x, y, true) {
+            return;
+        }
+
+        // Without gravity, there's no way to flow
+        let down = match forces.gravity_direction() {
+            Some(down) => down,
+            None => return,
+        };
+
         let i = space.get_index(x as u32, y as u32);
         // Randomly choose left or right for the horizontal movement
-        let d = if rand() > 0.5 { 1 } else { -1 };
+        let d = if rng.rand() > 0.5 { 1 } else { -1 };
+        // "Below" is whichever way gravity is pulling, so turn it to get the diagonals and sides
+        let offsets = [
+            down,                               // Directly below
+            rotate_direction(down, d),          // Diagonally below
+            rotate_direction(down, -d),         // Other diagonal
+            rotate_direction(down, 2 * d),      // Side
+            rotate_direction(down, -2 * d),     // Other side
+        ];
 
         // Check these positions in order: below, diagonal, opposite diagonal, sides
-        let check = vec!(
-            (x, y + 1),           // Directly below
-            (x + d, y + 1),       // Diagonally below
-            (x - d, y + 1),       // Other diagonal
-            (x + d, y),           // Side
-            (x - d, y),           // Other side
-        );
+        let check = offsets.iter().map(|(dx, dy)| (x + dx, y + dy)).collect();
 
         // Try to swap with cells in these positions if possible
-        self.check_swap_from_list(space, i, check, SwappingSim::check_density);
+        if let Some((nx, ny)) = self.check_swap_from_list(space, forces, rng, i, check, SwappingSim::check_density) {
+            // A liquid that flows sideways keeps going in that direction for a while, which spreads it out faster
+            let (dx, dy) = (nx - x, ny - y);
+            if dx * down.0 + dy * down.1 == 0 {
+                let cell = space.get_cell_at(space.get_index(nx as u32, ny as u32));
+                let speed = (cell.vx * cell.vx + cell.vy * cell.vy).sqrt().max(LIQUID_SPREAD);
+                cell.vx = dx as f32 * speed;
+                cell.vy = dy as f32 * speed;
+            }
+        }
+    }
+
+    /// Moves the cell at (x, y) along its velocity, one cell at a time, for as long as it's moving into empty space
+    /// Gravity speeds the cell up as it falls, the wind pushes it (less so the denser it is), and friction slows
+    /// its sideways movement.  When it hits something, it stops moving in that direction, and some of its falling
+    /// speed can be turned into sideways speed (splash), so that a liquid poured from high up spreads out when it
+    /// lands.  Liquids slide further and splash, while granular materials quickly come to a stop.
+    /// Returns true if the cell moved
+    fn follow_velocity(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32, liquid: bool) -> bool {
+        let i = space.get_index(x as u32, y as u32);
+        let (friction, splash) = if liquid { (LIQUID_FRICTION, LIQUID_SPLASH) } else { (GRANULAR_FRICTION, 0.0) };
+
+        // A cell resting on something doesn't gather speed (otherwise it would "land" again every few ticks)
+        let resting = match forces.gravity_direction() {
+            Some((down_x, down_y)) => match space.get_index_checked(x + down_x, y + down_y) {
+                Some(below) => space.get_cell_type_at(below) != CellType::Empty,
+                None => true,
+            },
+            None => false,
+        };
+
+        let (gx, gy) = forces.gravity;
+        let gravity_squared = gx * gx + gy * gy;
+        let (fx, fy) = forces.force_at(x, y);
+        let (vx, vy) = {
+            let cell = space.get_cell_at(i);
+            let density = cell.get_properties().density as f32;
+
+            // Split the velocity into the part that's falling (along gravity) and the part that's going across it
+            let falling: f32 = if gravity_squared > 0.0 { (cell.vx * gx + cell.vy * gy) / gravity_squared } else { 0.0 };
+            let (across_x, across_y) = (cell.vx - falling * gx, cell.vy - falling * gy);
+            // Gravity speeds up the fall, unless the cell has already landed
+            let falling = if resting { falling.min(0.0) } else { falling + 1.0 };
+
+            cell.vx = falling * gx + across_x * friction + fx * WIND_EFFECT / density;
+            cell.vy = falling * gy + across_y * friction + fy * WIND_EFFECT / density;
+
+            // Limit the speed, keeping the direction
+            let speed = (cell.vx * cell.vx + cell.vy * cell.vy).sqrt();
+            if speed > MAX_VELOCITY {
+                cell.vx *= MAX_VELOCITY / speed;
+                cell.vy *= MAX_VELOCITY / speed;
+            }
+            (cell.vx, cell.vy)
+        };
+
+        let is_empty = |space: &Space, x: i32, y: i32| {
+            space.get_index_checked(x, y).map(|i| space.get_cell_type_at(i) == CellType::Empty).unwrap_or(false)
+        };
+
+        // Trace the path one cell at a time, so the cell doesn't jump through anything in its way
+        let steps = vx.abs().max(vy.abs()).round() as i32;
+        let (mut cx, mut cy) = (x, y);
+        let (mut hit_x, mut hit_y) = (false, false);
+        for step in 1..=steps {
+            let tx = x + (vx * step as f32 / steps as f32).round() as i32;
+            let ty = y + (vy * step as f32 / steps as f32).round() as i32;
+
+            if is_empty(space, tx, ty) {
+                (cx, cy) = (tx, ty);
+            } else if ty != cy && is_empty(space, cx, ty) {
+                // Blocked diagonally, but it can still fall straight down (or rise straight up)
+                hit_x = tx != cx;
+                cy = ty;
+            } else if tx != cx && is_empty(space, tx, cy) {
+                // Blocked vertically, but it can still slide sideways
+                hit_y = ty != cy;
+                cx = tx;
+            } else {
+                hit_x = tx != cx;
+                hit_y = ty != cy;
+            }
+
+            if hit_x || hit_y {
+                break;
+            }
+        }
+
+        // Move the cell to where it ended up (everything it passed through was empty, so a swap is enough)
+        let ni = space.get_index(cx as u32, cy as u32);
+        if ni != i {
+            space.swap_cells(i, ni);
+            space.update_cell_generation(ni);
+        }
+
+        // Hitting something stops the cell in that direction
+        let cell = space.get_cell_at(ni);
+        let lost_x = if hit_x { cell.vx } else { 0.0 };
+        let lost_y = if hit_y { cell.vy } else { 0.0 };
+        cell.vx -= lost_x;
+        cell.vy -= lost_y;
+
+        // Some of the falling speed that was lost is turned into sideways speed, in the direction it was already going
+        if gravity_squared > 0.0 {
+            let gravity = gravity_squared.sqrt();
+            let lost_falling = (lost_x * gx + lost_y * gy) / gravity;
+            if lost_falling > 0.0 {
+                // Turn gravity a quarter circle to get the direction across it
+                let (across_x, across_y) = (-gy / gravity, gx / gravity);
+                let direction = match cell.vx * across_x + cell.vy * across_y {
+                    across if across != 0.0 => across.signum(),
+                    _ => if rng.rand() > 0.5 { 1.0 } else { -1.0 },
+                };
+                cell.vx += direction * across_x * lost_falling * splash;
+                cell.vy += direction * across_y * lost_falling * splash;
+            }
+        }
+
+        ni != i
     }
 
     /// Handles movement for gases like propane
-    fn move_gas(&mut self, space: &mut Space, x: i32, y: i32) {
+    fn move_gas(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32) {
         let i = space.get_index(x as u32, y as u32);
-        // Get random horizontal and vertical movement
-        let dx = random_modifier();
-        let dy = random_modifier();
+        // Get random horizontal and vertical movement, which the wind pushes in its direction
+        let (fx, fy) = forces.force_at(x, y);
+        let dx = (random_modifier(rng) + drift(fx, rng)).clamp(-1, 1);
+        let dy = (random_modifier(rng) + drift(fy, rng)).clamp(-1, 1);
 
         // Try to move in the random direction
         let check = vec!(
@@ -210,17 +618,101 @@ impl SwappingSim {
         );
 
         // Try to swap with cells in these positions if possible
-        self.check_swap_from_list(space, i, check, SwappingSim::check_density);
+        self.check_swap_from_list(space, forces, rng, i, check, SwappingSim::check_density);
+    }
+
+    /// Sprouts the seed at the given position into a plant, if it's resting on sand and touching water
+    /// Returns true if it sprouted
+    fn sprout(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32) -> bool {
+        // Seeds need to be resting on sand (below is whichever way gravity is pulling)
+        let on_sand = match forces.gravity_direction() {
+            Some((down_x, down_y)) => space.get_index_checked(x + down_x, y + down_y)
+                .map(|below| space.get_cell_type_at(below) == CellType::Sand)
+                .unwrap_or(false),
+            None => false,
+        };
+        if !on_sand {
+            return false;
+        }
+
+        let mut watered = false;
+        self.foreach_neighbour(space, x, y, |cell, _| watered |= cell.cell_type == CellType::Water);
+        if watered && rng.rand() < SEED_SPROUT_CHANCE {
+            space.get_cell_at(space.get_index(x as u32, y as u32)).cell_type = CellType::Plant;
+            return true;
+        }
+        false
+    }
+
+    /// Grows the plant at the given position, if it's touching water
+    /// The plant drinks the water (which disappears), and a new cell of plant is added either to the top of
+    /// one of the branches above it, or diagonally out of the side (which starts a new branch), as long as
+    /// there's space (or water) to grow into
+    fn grow(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, x: i32, y: i32) {
+        // Find some water to drink
+        let mut water = None;
+        for (dx, dy) in [(0, 1), (-1, 0), (1, 0), (0, -1), (-1, 1), (1, 1), (-1, -1), (1, -1)] {
+            if let Some(ni) = space.get_index_checked(x + dx, y + dy) {
+                if space.get_cell_type_at(ni) == CellType::Water {
+                    water = Some(ni);
+                    break;
+                }
+            }
+        }
+        let water = match water {
+            Some(water) if rng.rand() < PLANT_GROWTH_CHANCE => water,
+            _ => return,
+        };
+
+        // Plants grow upwards, against gravity (or up the screen if there's no gravity)
+        let (down_x, down_y) = forces.gravity_direction().unwrap_or((0, 1));
+        let up = (-down_x, -down_y);
+        let d = if rng.rand() > 0.5 { 1 } else { -1 };
+        let up_diagonals = [rotate_direction(up, d), rotate_direction(up, -d)];
+
+        // Climb up through the plant to find the top of one of its branches (chosen at random), remembering
+        // the way up, in case a new branch grows out of the side instead
+        let mut path = vec![(x, y)];
+        while path.len() < MAX_PLANT_HEIGHT {
+            let (tx, ty) = path[path.len() - 1];
+            let above: Vec<(i32, i32)> = [up, up_diagonals[0], up_diagonals[1]].iter()
+                .map(|(dx, dy)| (tx + dx, ty + dy))
+                .filter(|(px, py)| space.get_index_checked(*px, *py).map(|i| space.get_cell_type_at(i) == CellType::Plant).unwrap_or(false))
+                .collect();
+            if above.is_empty() {
+                break;
+            }
+            path.push(above[(rng.rand() * above.len() as f64) as usize]);
+        }
+        if path.len() >= MAX_PLANT_HEIGHT {
+            return;
+        }
+
+        // Either grow a new branch diagonally out of the side of the plant, or make the branch taller
+        // Plants can grow through water too, so that seeds that sprout underwater can reach the surface
+        let ((fx, fy), (dx, dy)) = if rng.rand() < PLANT_BRANCH_CHANCE {
+            (path[(rng.rand() * path.len() as f64) as usize], up_diagonals[0])
+        } else {
+            (path[path.len() - 1], up)
+        };
+        // The plant can't grow into the water it's drinking (which would be emptied again straight away)
+        if let Some(ni) = space.get_index_checked(fx + dx, fy + dy) {
+            if ni != water && matches!(space.get_cell_type_at(ni), CellType::Empty | CellType::Water) {
+                space.get_cell_at(ni).init(CellType::Plant, rng);
+                space.update_cell_generation(ni);
+                space.get_cell_at(water).init(CellType::Empty, rng);
+            }
+        }
     }
 
     /// Creates a new cell at the specified position
-    fn spawn_new(&mut self, space: &mut Space, x: i32, y: i32, cell: Cell) {
+    fn spawn_new(&mut self, space: &mut Space, rng: &mut dyn Random, x: i32, y: i32, cell: Cell) {
         // Randomly choose directions
-        let dx = if rand() > 0.5 { 1 } else { -1 };
-        let dy = if rand() > 0.5 { 1 } else { -1 };
+        let dx = if rng.rand() > 0.5 { 1 } else { -1 };
+        let dy = if rng.rand() > 0.5 { 1 } else { -1 };
 
         // Check these diagonal positions
-        let list = vec![
+        let list = [
             (x + dx, y + dy),
             (x - dx, y + dy),
             (x + dx, y - dy),
@@ -239,31 +731,94 @@ impl SwappingSim {
         }
     }
 
-    /// Attempts to ignite flammable neighbors around hot cells
-    fn ignite_neighbours(&mut self, temp: f32, space: &mut Space, x: i32, y: i32) {
-        /*
-        // This code is commented out in the original - it would simulate heat transfer
-        let i = space.get_index(x as u32, y as u32);
+    /// Sets the cell at the given position on fire
+    /// The new fire is at least as hot as the cell was when it ignited, and some materials (like wood)
+    /// have a chance of giving off a puff of smoke into an empty space next to them
+    fn ignite(&mut self, space: &mut Space, rng: &mut dyn Random, x: i32, y: i32) {
+        let cell = space.get_cell_at(space.get_index(x as u32, y as u32));
+        let (temp, smoke) = (cell.temp, cell.get_properties().smoke);
+        cell.init(CellType::Fire, rng);
+        cell.temp = cell.temp.max(temp);
+
+        if smoke > 0.0 && rng.rand() < smoke {
+            let mut cell = Cell::empty();
+            cell.init(CellType::Smoke, rng);
+            self.spawn_new(space, rng, x, y, cell);
+        }
+    }
 
-        let coefficient = CellType::get_properties(space.get_cell_type_at(i)).temp_coefficient;
-        let mut total_temp = 0.0;
-        self.foreach_neighbour(space, x, y, |cell, props| {
-            let change = (temp - cell.temp) * 0.1 * (props.temp_coefficient / coefficient);
-            cell.temp += change;
-            total_temp += change;
-        });
+    /// Detonates the cell at the given position
+    /// Everything near the center of the blast is destroyed and replaced with fire, and everything
+    /// else within the blast radius is heated and pushed outwards (into empty space).  Other explosive
+    /// cells caught in the blast are heated past their ignition temperature, so they explode in turn
+    fn explode(&mut self, space: &mut Space, rng: &mut dyn Random, x: i32, y: i32) {
+        // Visit the cells from the outside of the blast inwards, so that the cells being pushed out
+        // don't block the ones behind them
+        let mut offsets = vec![];
+        for dy in -EXPLOSION_RADIUS..=EXPLOSION_RADIUS {
+            for dx in -EXPLOSION_RADIUS..=EXPLOSION_RADIUS {
+                if dx * dx + dy * dy <= EXPLOSION_RADIUS * EXPLOSION_RADIUS {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        offsets.sort_by_key(|(dx, dy)| -(dx * dx + dy * dy));
+
+        for (dx, dy) in offsets {
+            let ni = match space.get_index_checked(x + dx, y + dy) {
+                Some(ni) => ni,
+                None => continue,
+            };
+
+            // The heat of the blast falls off with distance.  Cells are heated up to the blast temperature
+            // rather than having it added, so that overlapping explosions don't stack up endlessly
+            let distance = ((dx * dx + dy * dy) as f32).sqrt();
+            let heat = EXPLOSION_HEAT * (1.0 - distance / (EXPLOSION_RADIUS + 1) as f32);
+            let cell = space.get_cell_at(ni);
+
+            if dx * dx + dy * dy <= EXPLOSION_CORE_RADIUS * EXPLOSION_CORE_RADIUS {
+                // Everything in the core of the blast is destroyed
+                cell.init(CellType::Fire, rng);
+                cell.temp = cell.temp.max(heat);
+                space.update_cell_generation(ni);
+            } else {
+                cell.temp = cell.temp.max(heat);
+                if cell.cell_type != CellType::Empty {
+                    self.push_away(space, x + dx, y + dy, dx, dy, EXPLOSION_RADIUS + 1 - distance as i32);
+                }
+            }
+        }
+    }
 
-        let cell = space.get_cell_at(i);
-        cell.temp -= total_temp;
-        */
+    /// Moves the cell at the given position by up to the given distance in the direction of (dx, dy),
+    /// for as long as there is empty space to move into
+    fn push_away(&mut self, space: &mut Space, x: i32, y: i32, dx: i32, dy: i32, distance: i32) {
+        let length = ((dx * dx + dy * dy) as f32).sqrt();
+        let mut i = space.get_index(x as u32, y as u32);
+
+        for step in 1..=distance {
+            let nx = x + (dx as f32 * step as f32 / length).round() as i32;
+            let ny = y + (dy as f32 * step as f32 / length).round() as i32;
+            match space.get_index_checked(nx, ny) {
+                Some(ni) if ni == i => continue,
+                Some(ni) if space.get_cell_type_at(ni) == CellType::Empty => {
+                    space.swap_cells(i, ni);
+                    i = ni;
+                },
+                _ => break,
+            }
+        }
+        space.update_cell_generation(i);
+    }
 
-        // Check each neighboring cell
+    /// Adds heat to all the neighbours of a cell (for cells that produce heat rather than just exchanging it)
+    /// Neighbours with a higher temp_coefficient heat up faster.  Neighbours of the same type aren't heated,
+    /// otherwise a large fire would keep heating itself up without limit
+    fn heat_neighbours(&mut self, space: &mut Space, x: i32, y: i32, heat: f32) {
+        let cell_type = space.get_cell_type(x as u32, y as u32);
         self.foreach_neighbour(space, x, y, |cell, props| {
-            //if props.flammable && cell.temp > 100.0 {
-            // If the neighbor is flammable and we pass a random check
-            if props.flammable && rand() < 0.50 {
-                // Ignite it (turn it into fire)
-                cell.init(CellType::Fire);
+            if cell.cell_type != cell_type {
+                cell.temp += heat * props.temp_coefficient / (1.0 + props.temp_coefficient);
             }
         });
     }
@@ -274,7 +829,9 @@ impl SwappingSim {
     }
 
     /// Tries to swap the cell at index i with one of the cells in the provided list
-    fn check_swap_from_list(&mut self, space: &mut Space, i: usize, list: Vec<(i32, i32)>, can_move: fn(&CellTypeProperties, &CellTypeProperties) -> bool) {
+    /// If the cell is sinking into another (non-empty) cell, it will try to push that cell aside instead of swapping
+    /// Returns the position the cell moved to, if it moved
+    fn check_swap_from_list(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, i: usize, list: Vec<(i32, i32)>, can_move: fn(&CellTypeProperties, &CellTypeProperties) -> bool) -> Option<(i32, i32)> {
         for (x, y) in list.iter() {
             // Check if this position is within bounds
             if let Some(ni) = space.get_index_checked(*x, *y) {
@@ -282,13 +839,94 @@ impl SwappingSim {
                 let i_prop = CellType::get_properties(space.get_cell_type_at(i));
                 let ni_prop = CellType::get_properties(space.get_cell_type_at(ni));
                 // Check if the swap is allowed
-                if can_move(&i_prop, &ni_prop) {
+                if can_move(i_prop, ni_prop) {
+                    // A cell sinking into a liquid pushes the liquid aside rather than trading places with it,
+                    // otherwise the liquid gets carried up through a falling column of cells (eg. pouring sand into water)
+                    let sinking = match forces.gravity_direction() {
+                        Some((down_x, down_y)) => {
+                            let (ix, iy) = ((i as u32 % space.get_width()) as i32, (i as u32 / space.get_width()) as i32);
+                            (x - ix) * down_x + (y - iy) * down_y > 0
+                        },
+                        None => false,
+                    };
+                    if sinking && ni_prop.cell_type != CellType::Empty && self.displace(space, forces, rng, i, *x, *y) {
+                        return Some((*x, *y));
+                    }
+
                     // Swap the cells
                     space.swap_cells(i, ni);
-                    break;
+                    return Some((*x, *y));
                 }
             } 
         }
+        None
+    }
+
+    /// Moves the cell at index i into the position (x, y), and pushes the cell that was there out of the way
+    /// The pushed cell moves into a cell that it's denser than (eg. empty space), found by searching through the
+    /// connected cells of the same type (eg. the rest of a pool of water), and that cell in turn moves into the
+    /// space left at index i.  The lowest such cell (in the direction of gravity) is chosen, so that a liquid spreads out along its surface
+    /// rather than being pushed up the column of falling cells.  Returns false (and leaves everything as it
+    /// was) if there's nowhere to push the cell
+    fn displace(&mut self, space: &mut Space, forces: &ForceField, rng: &mut dyn Random, i: usize, x: i32, y: i32) -> bool {
+        let ni = space.get_index(x as u32, y as u32);
+        let pushed_type = space.get_cell_type_at(ni);
+        let pushed_prop = CellType::get_properties(pushed_type);
+        // Randomly choose which side to search first
+        let d = if rng.rand() > 0.5 { 1 } else { -1 };
+        let (down_x, down_y) = forces.gravity_direction().unwrap_or((0, 1));
+
+        // Breadth-first search outwards from the pushed cell, stopping after a limited number of cells
+        let mut visited = vec![ni];
+        let mut next = 0;
+        let mut found: Option<(i32, usize)> = None;
+        while next < visited.len() && visited.len() < MAX_DISPLACE_SEARCH {
+            let ci = visited[next];
+            let (cx, cy) = ((ci as u32 % space.get_width()) as i32, (ci as u32 / space.get_width()) as i32);
+            next += 1;
+
+            for (sx, sy) in [(cx + d, cy), (cx - d, cy), (cx, cy + 1), (cx, cy - 1)] {
+                if let Some(si) = space.get_index_checked(sx, sy) {
+                    if si == i || visited.contains(&si) {
+                        continue;
+                    }
+
+                    let si_type = space.get_cell_type_at(si);
+                    if SwappingSim::check_density(pushed_prop, CellType::get_properties(si_type)) {
+                        let depth = sx * down_x + sy * down_y;
+                        if found.map(|(fdepth, _)| depth > fdepth).unwrap_or(true) {
+                            found = Some((depth, si));
+                        }
+                    } else if si_type == pushed_type {
+                        visited.push(si);
+                    }
+                }
+            }
+        }
+
+        match found {
+            Some((_, si)) => {
+                space.cycle_cells(i, ni, si);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Applies a function to the index of each neighbor of a cell, for when the function needs to change the
+    /// space itself (eg. marking the neighbour as updated) rather than just the neighbouring cell
+    fn foreach_neighbour_index<F>(&mut self, space: &mut Space, x: i32, y: i32, mut f: F)
+        where F: FnMut(&mut Space, usize) {
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                if let Some(ni) = space.get_index_checked(x + dx, y + dy) {
+                    f(space, ni);
+                }
+            }
+        }
     }
 
     /// Applies a function to each neighbor of a cell
@@ -298,7 +936,7 @@ impl SwappingSim {
         for dx in -1..=1 {
             for dy in -1..=1 {
                 // Skip the center cell (which is the cell we're currently processing)
-                if dx == x && dy == y {
+                if dx == 0 && dy == 0 {
                     continue;
                 }
 
@@ -316,39 +954,87 @@ impl SwappingSim {
 }
 
 /// Defines a pattern to match against for cellular automaton rules
-enum MatchCell {
+/// Patterns only look at the types of the cells in the neighbourhood, never their temperature
+pub(crate) enum MatchCell {
     /// Matches exactly the specified cell type
     Exact(CellType),
+    /// Matches any cell type that moves in the specified way (eg. any liquid)
+    Class(Movement),
+    /// Matches any cell type that can catch fire
+    Flammable,
+    /// Matches any cell type that explodes when it catches fire
+    Explosive,
+    /// Matches any cell type that can be dissolved by acid
+    Dissolvable,
+    /// Matches any cell type that's less dense than the cell at the given position in the neighbourhood
+    Lighter(usize),
+    /// Matches any cell type that's more dense than the cell at the given position in the neighbourhood
+    Denser(usize),
+    /// Matches the same cell type as the cell at the given position in the neighbourhood
+    SameAs(usize),
+    /// Matches anything that the given pattern doesn't match
+    Not(Box<MatchCell>),
+    /// Matches anything that at least one of the given patterns matches
+    AnyOf(Vec<MatchCell>),
+    /// Matches only what all of the given patterns match
+    AllOf(Vec<MatchCell>),
     /// Matches any cell type
     Any,
 }
 
 /// Defines how to modify a cell for cellular automaton rules
-enum ModifyCell {
-    /// Keep the cell type the same
+pub(crate) enum ModifyCell {
+    /// Keep the cell the same
     Same,
-    /// Change the cell type to the specified type
+    /// Change the cell type to the specified type (the cell keeps its temperature, but starts a new lifetime
+    /// for its new type, just like a cell that changes state in SwappingSim)
     Type(CellType),
+    /// Move the cell from the given position in the neighbourhood into this position, along with its temperature
+    /// The positions are numbered 0 (top left), 1 (top right), 2 (bottom left), and 3 (bottom right)
+    Move(usize),
 }
 
 impl MatchCell {
-    /// Checks if this pattern matches the given cell
-    fn match_cell(&self, cell: Cell) -> bool {
+    /// Checks if this pattern matches the given cell type, which is one of the types in the neighbourhood
+    fn match_cell(&self, cell_type: CellType, types: &[CellType; 4]) -> bool {
+        let props = CellType::get_properties(cell_type);
         match self {
-            MatchCell::Exact(cell_type) => {
-                *cell_type == cell.cell_type
+            MatchCell::Exact(exact_type) => {
+                *exact_type == cell_type
+            },
+            MatchCell::Class(movement) => {
+                props.movement == *movement
+            },
+            MatchCell::Flammable => props.flammable,
+            MatchCell::Explosive => props.explosive,
+            MatchCell::Dissolvable => props.dissolvable,
+            MatchCell::Lighter(other) => {
+                props.density < CellType::get_properties(types[*other]).density
+            },
+            MatchCell::Denser(other) => {
+                props.density > CellType::get_properties(types[*other]).density
+            },
+            MatchCell::SameAs(other) => {
+                types[*other] == cell_type
             },
+            MatchCell::Not(pattern) => !pattern.match_cell(cell_type, types),
+            MatchCell::AnyOf(patterns) => patterns.iter().any(|pattern| pattern.match_cell(cell_type, types)),
+            MatchCell::AllOf(patterns) => patterns.iter().all(|pattern| pattern.match_cell(cell_type, types)),
             MatchCell::Any => true,
         }
     }
 }
 
 impl ModifyCell {
-    /// Applies the modification to the given cell
-    fn set_cell(&self, mut cell: Cell) -> Cell {
+    /// Applies the modification to the given cell, which is one of the cells in the given neighbourhood
+    fn set_cell(&self, mut cell: Cell, nb: &NeighbourhoodCells, rng: &mut dyn Random) -> Cell {
         match self {
             ModifyCell::Type(cell_type) => {
-                cell.cell_type = *cell_type
+                cell.cell_type = *cell_type;
+                cell.start_lifetime(rng);
+            },
+            ModifyCell::Move(from) => {
+                cell = nb[*from]
             },
             ModifyCell::Same => { },
         }
@@ -359,12 +1045,12 @@ impl ModifyCell {
 /// A 2x2 neighborhood of cells
 type NeighbourhoodCells = [Cell; 4];
 /// A pattern to match against a 2x2 neighborhood
-type NeighbourhoodPattern = [MatchCell; 4];
+pub(crate) type NeighbourhoodPattern = [MatchCell; 4];
 /// A set of modifications to apply to a 2x2 neighborhood
-type NeighbourhoodModifier = [ModifyCell; 4];
+pub(crate) type NeighbourhoodModifier = [ModifyCell; 4];
 
 /// A rule for cellular automaton simulation
-struct CellRule {
+pub(crate) struct CellRule {
     /// Probability (0-1) that this rule will be applied when matched
     probability: f64,
     /// Pattern to match against
@@ -375,7 +1061,7 @@ struct CellRule {
 
 impl CellRule {
     /// Creates a new cell rule
-    const fn new(probability: f64, if_nb: NeighbourhoodPattern, then_nb: NeighbourhoodModifier) -> CellRule {
+    pub(crate) const fn new(probability: f64, if_nb: NeighbourhoodPattern, then_nb: NeighbourhoodModifier) -> CellRule {
         CellRule {
             probability,
             if_nb,
@@ -383,143 +1069,444 @@ impl CellRule {
         }
     }
 
-    /// Checks if this rule's pattern matches the given neighborhood
-    fn match_if(&self, nb: NeighbourhoodCells) -> bool {
+    /// Creates a rule that only looks at two of the cells in the neighbourhood (any two cells in a 2x2 block
+    /// are touching), and modifies just those two cells.  The rest of the neighbourhood can be anything
+    fn pair(probability: f64, (i, if_i, then_i): (usize, MatchCell, ModifyCell), (j, if_j, then_j): (usize, MatchCell, ModifyCell)) -> CellRule {
+        let mut if_nb = [MatchCell::Any, MatchCell::Any, MatchCell::Any, MatchCell::Any];
+        let mut then_nb = [ModifyCell::Same, ModifyCell::Same, ModifyCell::Same, ModifyCell::Same];
+        if_nb[i] = if_i;
+        if_nb[j] = if_j;
+        then_nb[i] = then_i;
+        then_nb[j] = then_j;
+        CellRule::new(probability, if_nb, then_nb)
+    }
+
+    /// Creates a rule that swaps the cells at positions i and j, if they match the given patterns
+    fn swap(probability: f64, (i, if_i): (usize, MatchCell), (j, if_j): (usize, MatchCell)) -> CellRule {
+        CellRule::pair(probability, (i, if_i, ModifyCell::Move(j)), (j, if_j, ModifyCell::Move(i)))
+    }
+
+    /// Checks if this rule's pattern matches the given cell types
+    /// (the probability is checked separately, so that it's only checked once for the whole rule)
+    fn match_if(&self, types: [CellType; 4]) -> bool {
         self.if_nb.iter()
-            .zip(nb.iter())
-            .all(|(pattern, cell)| pattern.match_cell(*cell) && rand() < self.probability)
+            .zip(types.iter())
+            .all(|(pattern, cell_type)| pattern.match_cell(*cell_type, &types))
+    }
+
+    /// Checks that this rule doesn't change any of the locked cells, and doesn't move any cells
+    /// from outside the space (which is where the neighbourhood hangs over the edge)
+    fn can_apply(&self, locked: [bool; 4], outside: [bool; 4]) -> bool {
+        self.then_nb.iter()
+            .zip(locked.iter())
+            .all(|(modify, locked)| match modify {
+                ModifyCell::Same => true,
+                ModifyCell::Type(_) => !locked,
+                ModifyCell::Move(from) => !locked && !outside[*from],
+            })
+    }
+
+    /// Returns which of the cells in the neighbourhood this rule changes
+    fn changes(&self) -> [bool; 4] {
+        let mut changes = [false; 4];
+        for (changed, modify) in changes.iter_mut().zip(self.then_nb.iter()) {
+            *changed = !matches!(modify, ModifyCell::Same);
+        }
+        changes
     }
 
     /// Applies this rule's modifications to the given neighborhood
-    fn set_then(&self, nb: NeighbourhoodCells) -> NeighbourhoodCells {
+    fn set_then(&self, nb: NeighbourhoodCells, rng: &mut dyn Random) -> NeighbourhoodCells {
         self.then_nb.iter()
             .zip(nb.iter())
-            .map(|(pattern, cell)| pattern.set_cell(*cell))
+            .map(|(pattern, cell)| pattern.set_cell(*cell, &nb, rng))
             .collect::<Vec<Cell>>()
             .try_into()
             .unwrap()
     }
 }
 
-const RULES: &[CellRule] = &[
-    CellRule::new(1.0, [
-        MatchCell::Exact(CellType::Sand), MatchCell::Any,
-        MatchCell::Exact(CellType::Empty), MatchCell::Any,
-    ],
-    [
-        ModifyCell::Type(CellType::Empty), ModifyCell::Same,
-        ModifyCell::Type(CellType::Sand), ModifyCell::Same,
-    ]),
-    CellRule::new(1.0, [
-        MatchCell::Any, MatchCell::Exact(CellType::Sand),
-        MatchCell::Any, MatchCell::Exact(CellType::Empty),
-    ],
-    [
-        ModifyCell::Same, ModifyCell::Type(CellType::Empty),
-        ModifyCell::Same, ModifyCell::Type(CellType::Sand),
-    ]),
-    CellRule::new(1.0, [
-        MatchCell::Exact(CellType::Sand), MatchCell::Any,
-        MatchCell::Exact(CellType::Sand), MatchCell::Exact(CellType::Empty),
-    ],
-    [
-        ModifyCell::Type(CellType::Empty), ModifyCell::Same,
-        ModifyCell::Type(CellType::Sand),  ModifyCell::Type(CellType::Sand),
-    ]),
-    CellRule::new(1.0, [
-        MatchCell::Any,                    MatchCell::Exact(CellType::Sand),
-        MatchCell::Exact(CellType::Empty), MatchCell::Exact(CellType::Sand),
-    ],
-    [
-        ModifyCell::Same,                 ModifyCell::Type(CellType::Empty),
-        ModifyCell::Type(CellType::Sand), ModifyCell::Type(CellType::Sand),
-    ]),
+/// The pairs of positions in a 2x2 neighbourhood where the first cell is directly above the second
+const ABOVE: [(usize, usize); 2] = [(0, 2), (1, 3)];
+/// The pairs of positions in a 2x2 neighbourhood where the first cell is diagonally above the second
+const DIAGONALLY_ABOVE: [(usize, usize); 2] = [(0, 3), (1, 2)];
+/// The pairs of positions in a 2x2 neighbourhood where the cells are side by side
+const BESIDE: [(usize, usize); 4] = [(0, 1), (1, 0), (2, 3), (3, 2)];
+/// Every pair of different positions in a 2x2 neighbourhood (all of which are touching)
+const TOUCHING: [(usize, usize); 12] = [
+    (0, 1), (0, 2), (0, 3), (1, 0), (1, 2), (1, 3),
+    (2, 0), (2, 1), (2, 3), (3, 0), (3, 1), (3, 2),
 ];
 
+/// Builds the rules for all the cell types, based on their properties
+/// The rules are tried in order, so the reactions come first, then falling, and then the sideways movements,
+/// which means a rule for sliding sideways is only reached if the cell couldn't fall instead
+fn build_rules() -> Vec<CellRule> {
+    let mut rules = vec![];
+
+    // Granular materials and liquids are the cells that fall
+    let falling = || MatchCell::AnyOf(vec![MatchCell::Class(Movement::Granular), MatchCell::Class(Movement::Liquid)]);
+    // Cells can be pushed out of the way if they're empty space or anything that isn't static
+    let movable = || MatchCell::AnyOf(vec![MatchCell::Exact(CellType::Empty), MatchCell::Not(Box::new(MatchCell::Class(Movement::Static)))]);
+
+    // Fire and lava are what set things on fire.  This simulator doesn't keep track of temperatures, so it
+    // can't wait for things to heat up to their ignition temperature, and they catch fire by chance instead
+    let burning = || MatchCell::AnyOf(vec![MatchCell::Exact(CellType::Fire), MatchCell::Exact(CellType::Lava)]);
+    for (i, j) in TOUCHING {
+        // Explosive cells catch much more easily than other flammable ones
+        rules.push(CellRule::pair(0.5, (i, burning(), ModifyCell::Same), (j, MatchCell::Explosive, ModifyCell::Type(CellType::Fire))));
+        rules.push(CellRule::pair(0.1, (i, burning(), ModifyCell::Same), (j, MatchCell::Flammable, ModifyCell::Type(CellType::Fire))));
+    }
+
+    // Reactions between two touching cells, from the reaction table in materials.txt (eg. water cooling lava
+    // into rock).  The rules only look at the types of the cells, so the temperature range and heat are ignored
+    let product = |product: Option<CellType>| product.map_or(ModifyCell::Same, ModifyCell::Type);
+    for (i, j) in TOUCHING {
+        for cell_type in CellType::iter() {
+            for reaction in CellType::get_properties(*cell_type).reactions.iter() {
+                let other = match reaction.other {
+                    Reactant::Type(other_type) => MatchCell::Exact(other_type),
+                    Reactant::Flammable => MatchCell::Flammable,
+                    Reactant::Explosive => MatchCell::Explosive,
+                    Reactant::Dissolvable => MatchCell::Dissolvable,
+                };
+                rules.push(CellRule::pair(reaction.probability,
+                    (i, MatchCell::Exact(*cell_type), product(reaction.becomes)),
+                    (j, other, product(reaction.other_becomes))));
+            }
+        }
+    }
+
+    // Changes that happen to a cell on its own
+    for i in 0..4 {
+        let mut changes = |probability, from, to| {
+            let mut if_nb = [MatchCell::Any, MatchCell::Any, MatchCell::Any, MatchCell::Any];
+            let mut then_nb = [ModifyCell::Same, ModifyCell::Same, ModifyCell::Same, ModifyCell::Same];
+            if_nb[i] = MatchCell::Exact(from);
+            then_nb[i] = ModifyCell::Type(to);
+            rules.push(CellRule::new(probability, if_nb, then_nb));
+        };
+        changes(0.01, CellType::Fire, CellType::Smoke);     // Fire sometimes leaves smoke as it burns out
+        changes(0.02, CellType::Fire, CellType::Empty);     // Fire burns out
+        changes(0.0005, CellType::Lava, CellType::Rock);    // Lava slowly cools into rock
+        changes(0.001, CellType::Steam, CellType::Water);   // Steam slowly condenses back into water
+        changes(0.005, CellType::Smoke, CellType::Empty);   // Smoke slowly fades away
+    }
+
+    // Granular materials and liquids fall straight down into anything lighter that can move out of the way,
+    // while heavier gases only sometimes sink below lighter ones
+    for (i, j) in ABOVE {
+        rules.push(CellRule::swap(1.0, (i, MatchCell::AllOf(vec![falling(), MatchCell::Denser(j)])), (j, movable())));
+        rules.push(CellRule::swap(0.5, (i, MatchCell::AllOf(vec![MatchCell::Class(Movement::Gas), MatchCell::Denser(j)])), (j, MatchCell::Class(Movement::Gas))));
+    }
+
+    // If they can't fall straight down, granular materials and liquids slide diagonally
+    for (i, j) in DIAGONALLY_ABOVE {
+        rules.push(CellRule::swap(0.5, (i, MatchCell::AllOf(vec![falling(), MatchCell::Denser(j)])), (j, movable())));
+    }
+
+    // Liquids also flow sideways into lighter cells, to spread out
+    for (i, j) in BESIDE {
+        rules.push(CellRule::swap(0.5, (i, MatchCell::AllOf(vec![MatchCell::Class(Movement::Liquid), MatchCell::Denser(j)])), (j, movable())));
+    }
+
+    // Gases drift around randomly in empty space, in every direction
+    for (i, j) in TOUCHING {
+        rules.push(CellRule::swap(0.25, (i, MatchCell::Class(Movement::Gas)), (j, MatchCell::Exact(CellType::Empty))));
+    }
+
+    rules
+}
+
 /// Alternative simulation implementation using cellular automaton rules
-pub struct CellularSim;
+/// The space is divided into 2x2 blocks (a Margolus neighbourhood), and each block is changed according
+/// to the first rules that match it.  The blocks are shifted by one cell every other tick, so that cells can
+/// move between blocks.  It doesn't use the forces, so gravity always pulls straight down
+pub struct CellularSim {
+    /// All the rules, in the order they're tried
+    rules: Vec<CellRule>,
+    /// The rules that could match each combination of 4 cell types, which is looked up the first time
+    /// each combination is seen.  Most blocks match no rules at all (eg. empty space), so this saves
+    /// checking every rule for every block
+    candidates: HashMap<u32, Vec<usize>>,
+}
+
+impl Default for CellularSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Simulator for CellularSim {
-    fn tick(&mut self, space: &mut Space) {
-        self.advance(space);
+    fn tick(&mut self, space: &mut Space, _forces: &ForceField, rng: &mut dyn Random) {
+        self.advance(space, rng);
     }
 }
 
 impl CellularSim {
+    /// Creates a new cellular automaton simulator with the rules for all the cell types
+    pub fn new() -> CellularSim {
+        CellularSim {
+            rules: build_rules(),
+            candidates: HashMap::new(),
+        }
+    }
+
+    /// Creates a new cellular automaton simulator that uses rules written as text (see rules.rs for the format)
+    /// instead of the built-in rules.  Returns an error describing the first problem found in the text
+    pub fn with_rules(text: &str) -> Result<CellularSim, RuleError> {
+        Ok(CellularSim {
+            rules: parse_rules(text)?,
+            candidates: HashMap::new(),
+        })
+    }
+
     /// Advances the cellular automaton simulation by one step
-    pub fn advance(&mut self, space: &mut Space) {
+    pub fn advance(&mut self, space: &mut Space, rng: &mut dyn Random) {
         space.increment_generation();
-        let start = if space.get_generation() % 2 == 0 { 0 } else { 1 };
+        // On odd ticks the blocks are shifted up and to the left by one cell, so the blocks along the
+        // edges hang over the edge of the space, and the cells outside the space act like solid walls
+        let start = if space.get_generation() % 2 == 0 { 0 } else { -1 };
 
-        for y in (start..(space.get_height() as i32 - start)).step_by(2) {
-            for x in (start..(space.get_width() as i32 - start)).step_by(2) {
+        for y in (start..(space.get_height() as i32)).step_by(2) {
+            for x in (start..(space.get_width() as i32)).step_by(2) {
                 // The 2 x 2 grid start at (x, y) needs to be evaluated
-
-                let mut square = self.get_neighbourhood(space, x, y);
-
-                for rule in RULES {
-                    if rule.match_if(square) {
-                        square = rule.set_then(square);
-                        break;
+                let (mut square, outside) = self.get_neighbourhood(space, x, y);
+
+                // Apply every rule that matches (in order), but only change each cell once per tick
+                let mut locked = outside;
+                let mut next = 0;
+                let mut changed = false;
+                while let Some(index) = self.find_rule(square, locked, outside, next, rng) {
+                    let rule = &self.rules[index];
+                    square = rule.set_then(square, rng);
+                    for (locked, changes) in locked.iter_mut().zip(rule.changes()) {
+                        *locked |= changes;
                     }
+                    next = index + 1;
+                    changed = true;
                 }
 
-/*
-                // Falling downwards rule (Gravity)
-                if square[0][0].1.density > square[1][0].1.density {
-                    swap!(square[0][0], square[1][0]);
+                if changed {
+                    self.set_neighbourhood(space, x, y, &square, outside);
                 }
-                if square[0][1].1.density > square[1][1].1.density {
-                    swap!(square[0][1], square[1][1]);
-                }
-
-                // Falling sideways rule (Sand)
-                if square[0][0].1.density > square[1][1].1.density {
-                    swap!(square[0][0], square[1][1]);
-                }
-                if square[0][1].1.density > square[1][0].1.density {
-                    swap!(square[0][1], square[1][0]);
-                }
-                // Moving sideways rule (Water)
-                if (square[0][0].0.cell_type == CellType::Water || square[0][0].0.cell_type == CellType::Empty)
-                  && (square[0][1].0.cell_type == CellType::Water || square[0][1].0.cell_type == CellType::Empty) {
-                    swap!(square[0][0], square[0][1]);
-                }
-                if (square[1][0].0.cell_type == CellType::Water || square[1][0].0.cell_type == CellType::Empty)
-                  && (square[1][1].0.cell_type == CellType::Water || square[1][1].0.cell_type == CellType::Empty) {
-                    swap!(square[1][0], square[1][1]);
-                }
-*/
-
-                self.set_neighbourhood(space, x, y, &square);
             }
         }
     }
 
-    /// Gets the 2x2 neighborhood of cells at the specified position
-    fn get_neighbourhood(&self, space: &mut Space, x: i32, y: i32) -> [Cell; 4] {
-        let cell1a = *space.get_cell_at(space.get_index(x as u32, y as u32));
-        let cell1b = *space.get_cell_at(space.get_index(x as u32 + 1, y as u32));
-        let cell2a = *space.get_cell_at(space.get_index(x as u32, y as u32 + 1));
-        let cell2b = *space.get_cell_at(space.get_index(x as u32 + 1, y as u32 + 1));
-        [ cell1a, cell1b, cell2a, cell2b ]
+    /// Finds the first rule (starting from the given index) that matches the neighbourhood, doesn't change
+    /// any locked cells, and passes its random probability check
+    fn find_rule(&mut self, square: NeighbourhoodCells, locked: [bool; 4], outside: [bool; 4], next: usize, rng: &mut dyn Random) -> Option<usize> {
+        let types = [square[0].cell_type, square[1].cell_type, square[2].cell_type, square[3].cell_type];
+        let key = types.iter().fold(0, |key, cell_type| (key << 8) | *cell_type as u32);
+        let rules = &self.rules;
+        let candidates = self.candidates.entry(key).or_insert_with(|| {
+            (0..rules.len()).filter(|index| rules[*index].match_if(types)).collect()
+        });
+
+        candidates.iter()
+            .copied()
+            .filter(|index| *index >= next)
+            .find(|index| {
+                let rule = &rules[*index];
+                rule.can_apply(locked, outside) && (rule.probability >= 1.0 || rng.rand() < rule.probability)
+            })
+    }
+
+    /// Gets the 2x2 neighborhood of cells at the specified position, along with which of the cells are
+    /// outside the space (which are filled in with rock, so they act like walls)
+    fn get_neighbourhood(&self, space: &mut Space, x: i32, y: i32) -> (NeighbourhoodCells, [bool; 4]) {
+        let mut square = [Cell::empty(); 4];
+        let mut outside = [false; 4];
+        for (k, (dx, dy)) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter().enumerate() {
+            match space.get_index_checked(x + dx, y + dy) {
+                Some(i) => square[k] = *space.get_cell_at(i),
+                None => {
+                    square[k].cell_type = CellType::Rock;
+                    outside[k] = true;
+                },
+            }
+        }
+        (square, outside)
     }
 
-    /// Sets the 2x2 neighborhood of cells at the specified position
-    fn set_neighbourhood(&self, space: &mut Space, x: i32, y: i32, square: &[Cell; 4]) {
-        space.set_cell(space.get_index(x as u32, y as u32), &square[0]);
-        space.set_cell(space.get_index(x as u32 + 1, y as u32), &square[1]);
-        space.set_cell(space.get_index(x as u32, y as u32 + 1), &square[2]);
-        space.set_cell(space.get_index(x as u32 + 1, y as u32 + 1), &square[3]);
+    /// Sets the 2x2 neighborhood of cells at the specified position (skipping any cells outside the space)
+    fn set_neighbourhood(&self, space: &mut Space, x: i32, y: i32, square: &NeighbourhoodCells, outside: [bool; 4]) {
+        for (k, (dx, dy)) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter().enumerate() {
+            if !outside[k] {
+                space.set_cell(space.get_index((x + dx) as u32, (y + dy) as u32), &square[k]);
+            }
+        }
     }
 }
 
 /// Generates a random value for adding variation to simulation
-fn random_modifier() -> i32 {
-    match rand() {
+fn random_modifier(rng: &mut dyn Random) -> i32 {
+    match rng.rand() {
         x if x < 0.33 => 1,
         x if x < 0.66 => -1,
         _ => 0,
     }
 }
 
+/// Returns a step of 1 in the direction of the given force, with a chance equal to its strength
+/// (so a force of 0.5 moves a cell one step about every other tick), or 0 otherwise
+fn drift(force: f32, rng: &mut dyn Random) -> i32 {
+    if force != 0.0 && rng.rand() < force.abs() as f64 {
+        force.signum() as i32
+    } else {
+        0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRandom;
+
+    /// Fills the given rectangle of the space (from x0 to x1, and from y0 to y1, not including x1 or y1) with new cells
+    fn fill(space: &mut Space, rng: &mut dyn Random, (x0, x1): (u32, u32), (y0, y1): (u32, u32), cell_type: CellType) {
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let i = space.get_index(x, y);
+                space.get_cell_at(i).init(cell_type, rng);
+            }
+        }
+    }
+
+    /// Counts the cells of the given type in the space
+    fn count(space: &Space, cell_type: CellType) -> usize {
+        (0..space.get_height())
+            .flat_map(|y| (0..space.get_width()).map(move |x| (x, y)))
+            .filter(|&(x, y)| space.get_cell_type(x, y) == cell_type)
+            .count()
+    }
+
+    #[test]
+    fn snow_packs_into_ice() {
+        // A small pile of snow on the ground, in air at the ambient temperature
+        let mut space = Space::new(40, 30);
+        let forces = ForceField::new(40, 30);
+        let mut rng = SeededRandom::new(3);
+        fill(&mut space, &mut rng, (0, 40), (28, 30), CellType::Rock);
+        fill(&mut space, &mut rng, (18, 23), (25, 28), CellType::Snow);
+
+        // The snow has to pack down into ice before the warm air melts it all
+        let mut sim = SwappingSim;
+        for _ in 0..75 {
+            sim.tick(&mut space, &forces, &mut rng);
+        }
+        assert!(count(&space, CellType::Ice) > 0);
+    }
+
+    #[test]
+    fn water_freezes_in_cold_air() {
+        // The same pool of water sitting on the ground, in air at room temperature and in air below freezing
+        for (ambient_temp, frozen) in [(20.0, false), (-20.0, true)] {
+            let mut space = Space::new(10, 10);
+            let mut forces = ForceField::new(10, 10);
+            forces.ambient_temp = ambient_temp;
+            let mut rng = SeededRandom::new(5);
+            fill(&mut space, &mut rng, (0, 10), (9, 10), CellType::Rock);
+            fill(&mut space, &mut rng, (0, 10), (7, 9), CellType::Water);
+
+            let mut sim = SwappingSim;
+            for _ in 0..600 {
+                sim.tick(&mut space, &forces, &mut rng);
+            }
+            assert_eq!(count(&space, CellType::Ice) == 20, frozen);
+        }
+    }
+
+    #[test]
+    fn saltwater_freezes_after_water() {
+        // A pool of water and a pool of saltwater, with a wall between them, in air far below freezing
+        let mut space = Space::new(21, 10);
+        let mut forces = ForceField::new(21, 10);
+        forces.ambient_temp = -40.0;
+        let mut rng = SeededRandom::new(3);
+        fill(&mut space, &mut rng, (0, 21), (9, 10), CellType::Rock);
+        fill(&mut space, &mut rng, (10, 11), (4, 9), CellType::Rock);
+        fill(&mut space, &mut rng, (0, 10), (7, 9), CellType::Water);
+        fill(&mut space, &mut rng, (11, 21), (7, 9), CellType::Saltwater);
+
+        let mut sim = SwappingSim;
+        for _ in 0..350 {
+            sim.tick(&mut space, &forces, &mut rng);
+        }
+        // The water has frozen, but the saltwater hasn't yet
+        assert_eq!(count(&space, CellType::Water), 0);
+        assert_eq!(count(&space, CellType::Saltwater), 20);
+
+        for _ in 0..450 {
+            sim.tick(&mut space, &forces, &mut rng);
+        }
+        assert_eq!(count(&space, CellType::Saltwater), 0);
+        assert_eq!(count(&space, CellType::Ice), 40);
+    }
+
+    #[test]
+    fn changing_type_starts_a_new_lifetime() {
+        let mut rng = SeededRandom::new(7);
+        let nb = [Cell::empty(); 4];
+
+        // Steam that condenses into water doesn't keep the rest of the steam's lifetime (so it doesn't fade away)
+        let mut steam = Cell::empty();
+        steam.init(CellType::Steam, &mut rng);
+        let water = ModifyCell::Type(CellType::Water).set_cell(steam, &nb, &mut rng);
+        assert_eq!(water.lifetime, 0);
+
+        // Fire that leaves smoke behind gets a lifetime for the smoke (so it does fade away)
+        let mut fire = Cell::empty();
+        fire.init(CellType::Fire, &mut rng);
+        let smoke = ModifyCell::Type(CellType::Smoke).set_cell(fire, &nb, &mut rng);
+        assert!(smoke.lifetime > 0);
+    }
+
+    #[test]
+    fn sparks_only_set_fire_to_things_by_heating_them() {
+        // A layer of propane lying on a wire that's being sparked by a battery
+        let mut space = Space::new(21, 10);
+        let forces = ForceField::new(21, 10);
+        let mut rng = SeededRandom::new(3);
+        fill(&mut space, &mut rng, (0, 21), (9, 10), CellType::Rock);
+        fill(&mut space, &mut rng, (0, 1), (8, 9), CellType::Battery);
+        fill(&mut space, &mut rng, (1, 21), (8, 9), CellType::Metal);
+        fill(&mut space, &mut rng, (1, 21), (7, 8), CellType::Propane);
+
+        // Nothing catches fire while the wire is still warming up
+        let mut sim = SwappingSim;
+        for _ in 0..50 {
+            sim.tick(&mut space, &forces, &mut rng);
+        }
+        assert_eq!(count(&space, CellType::Propane), 20);
+
+        // But once the wire is hot enough, the propane goes up in flames
+        for _ in 0..250 {
+            sim.tick(&mut space, &forces, &mut rng);
+        }
+        assert!(count(&space, CellType::Propane) < 20);
+    }
+
+    #[test]
+    fn plants_dont_grow_into_the_water_they_drink() {
+        // A plant with nothing around it except water directly above it, so the only water it can drink is
+        // the cell it would grow into
+        let mut space = Space::new(3, 3);
+        let forces = ForceField::new(3, 3);
+        let mut rng = SeededRandom::new(11);
+        fill(&mut space, &mut rng, (0, 3), (0, 3), CellType::Rock);
+        fill(&mut space, &mut rng, (1, 2), (1, 2), CellType::Plant);
+        fill(&mut space, &mut rng, (1, 2), (0, 1), CellType::Water);
+
+        let mut sim = SwappingSim;
+        for _ in 0..100 {
+            sim.grow(&mut space, &forces, &mut rng, 1, 1);
+        }
+
+        // Nothing changes, rather than the water being turned into plant and then emptied
+        assert_eq!(space.get_cell_type(1, 0), CellType::Water);
+        assert_eq!(space.get_cell_type(1, 1), CellType::Plant);
+    }
+}
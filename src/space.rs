@@ -1,8 +1,73 @@
 //! This file defines the simulation space (grid) that contains all the cells.
 //! It manages the 2D grid layout and provides methods to access and manipulate cells.
+//!
+//! A space can also be saved into a compact binary format, and loaded back again.  All numbers are
+//! stored in little-endian order, and the format is:
+//!
+//! - the 4 bytes `FRSP`, which mark the data as a saved space
+//! - the version of the format, as 2 bytes (see SAVE_VERSION)
+//! - the width and height, as 4 bytes each, and the generation, as 1 byte
+//! - the number of materials, followed by the name of each material (its length, and then its letters)
+//! - the cells, in row-major order, as runs of identical cells.  Each run is its length, followed by the
+//!   cells' material (as a position in the list of names above), temperature (rounded to a whole degree),
+//!   remaining lifetime, and the material it emits (for clones and taps)
+//!
+//! All the numbers after the generation are written as variable-length integers (7 bits in each byte,
+//! with the top bit set on every byte except the last), so that small numbers only take one byte, and
+//! the temperature is zigzag-encoded first (0, -1, 1, -2, 2... become 0, 1, 2, 3, 4...) so that it can be
+//! negative.  Materials are saved by name rather than by their position in the CellType enum, so that saves
+//! keep working when materials are added or reordered, and a save that uses a material that doesn't exist
+//! (eg. one made by a newer version) still loads, with those cells left empty.
+//!
+//! Saves can also be compressed (with deflate), which is how they're kept in the browser's storage.
+
+use std::fmt;
+use std::convert::TryInto;
 
 // Import the Cell and CellType from our cells module
-use crate::cells::{ Cell, CellType };
+use crate::cells::{ Cell, CellType, CELL_TYPES };
+// Import the random number generator trait
+use crate::random::Random;
+// Import the function for logging messages
+use crate::log;
+
+/// The bytes at the start of every saved space
+const SAVE_MAGIC: &[u8; 4] = b"FRSP";
+/// The version of the save format written by Space::save()
+/// This must be increased whenever the format changes, and Space::load() can read any version up to this one
+pub const SAVE_VERSION: u16 = 1;
+/// The most cells that a loaded space can have (whether it's a saved space or an image), so that a damaged
+/// file can't make us run out of memory.  This is several times the size of the space the game makes (1024 by 640)
+pub const MAX_LOADED_CELLS: u64 = 1 << 22;
+/// How hard to try to make compressed saves smaller (from 0 to 10), where harder is slower
+const COMPRESSION_LEVEL: u8 = 6;
+/// The biggest that a compressed save can be once it's decompressed (the most cells, at up to 16 bytes
+/// each), so that a damaged save can't make us run out of memory
+const MAX_DECOMPRESSED_SIZE: usize = 1 << 26;
+
+/// An error found while loading a saved space
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadError {
+    /// A description of the problem
+    pub message: String,
+}
+
+impl LoadError {
+    /// Creates a new error with the given description
+    fn new(message: &str) -> LoadError {
+        LoadError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for LoadError { }
 
 /// The Space struct represents the entire simulation grid
 /// It keeps track of dimensions, all cells, and the current simulation generation
@@ -33,10 +98,10 @@ impl Space {
 
         // Create and return the Space struct
         Space {
-            width: width,
-            height: height,
+            width,
+            height,
             generation: 0,       // Start at generation 0
-            cells: cells,        // Our vector of cells
+            cells,               // Our vector of cells
         }
     }
 
@@ -59,7 +124,7 @@ impl Space {
     /// Increments the generation counter for the simulation
     /// This is called at the end of each simulation step
     pub fn increment_generation(&mut self) {
-        self.generation += 1;
+        self.generation = self.generation.wrapping_add(1);
     }
 
     /// Updates the generation of a specific cell to match the current simulation generation
@@ -94,7 +159,9 @@ impl Space {
 
     /// Adds a cell of the specified type at the given coordinates
     /// Used for placing cells with the mouse
-    pub fn add(&mut self, x: i32, y: i32, cell_type: CellType) {
+    /// Returns the index of every cell that was changed, along with what it was before (so it can be undone)
+    pub fn add(&mut self, x: i32, y: i32, cell_type: CellType, rng: &mut dyn Random) -> Vec<(usize, Cell)> {
+        let mut changed = vec![];
         let mut created = 0;
         // Loop through a 5x5 grid centered at (x,y)
         for dy in -2..=2 {   // -2, -1, 0, 1, 2
@@ -103,13 +170,21 @@ impl Space {
                 if let Some(i) = self.get_index_checked(x + dx, y + dy) {
                     // Only replace every other cell, and only if the target is empty
                     // or we're trying to erase (place empty cells)
+                    let before = self.cells[i];
                     if created % 2 == 0 && (self.cells[i].cell_type == CellType::Empty || cell_type == CellType::Empty) {
-                        self.cells[i].init(cell_type);
+                        self.cells[i].init(cell_type, rng);
+                    } else if self.cells[i].cell_type.is_emitter() && cell_type.can_be_emitted() {
+                        // Painting a material onto an emitter (eg. a tap) makes it emit that material
+                        self.cells[i].emits = cell_type;
+                    }
+                    if self.cells[i] != before {
+                        changed.push((i, before));
                     }
                     created += 1;
                 }
             }
         }
+        changed
     }
 
     /// Gets the cell type at the specified coordinates
@@ -118,11 +193,21 @@ impl Space {
         self.cells[i].cell_type
     }
 
+    /// Gets the cell at the specified coordinates
+    pub fn get_cell(&self, x: u32, y: u32) -> &Cell {
+        &self.cells[self.get_index(x, y)]
+    }
+
     /// Gets a mutable reference to the cell at the specified index
-    pub fn get_cell_at<'a>(&'a mut self, i: usize) -> &'a mut Cell {
+    pub fn get_cell_at(&mut self, i: usize) -> &mut Cell {
         &mut self.cells[i]
     }
 
+    /// Gets the cell at the specified index, without changing it
+    pub fn get_cell_ref_at(&self, i: usize) -> &Cell {
+        &self.cells[i]
+    }
+
     /// Gets the cell type at the specified index
     pub fn get_cell_type_at(&self, i: usize) -> CellType {
         self.cells[i].cell_type
@@ -131,17 +216,154 @@ impl Space {
     /// Swaps two cells in the grid
     /// Used by the simulation algorithm to move cells
     pub fn swap_cells(&mut self, i: usize, j: usize) {
-        // Store the cell at index i
-        let i_cell = self.cells[i];
-        // Replace cell at index i with cell at index j
-        self.cells[i] = self.cells[j];
-        // Replace cell at index j with the stored cell
-        self.cells[j] = i_cell;
+        // Exchange the two cells in place
+        self.cells.swap(i, j);
 
         // Mark the cell at index i as updated in this generation
         self.cells[i].generation = self.generation;
     }
 
+    /// Moves three cells around in a cycle: the cell at index i moves to j, the cell at j moves to k,
+    /// and the cell at k moves to i
+    /// Used by the simulation algorithm to push a cell aside when another moves into its place
+    pub fn cycle_cells(&mut self, i: usize, j: usize, k: usize) {
+        let k_cell = self.cells[k];
+        self.cells[k] = self.cells[j];
+        self.cells[j] = self.cells[i];
+        self.cells[i] = k_cell;
+
+        // Mark all three cells as updated in this generation, so none of them are moved again
+        self.cells[i].generation = self.generation;
+        self.cells[j].generation = self.generation;
+        self.cells[k].generation = self.generation;
+    }
+
+    /// Saves the space into the binary format described at the top of this file
+    /// Only the parts of each cell that matter between ticks are kept (its material, temperature, lifetime, and
+    /// what it emits).  Velocities and sparks are dropped, and temperatures are rounded to the nearest degree
+    pub fn save(&self) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(SAVE_MAGIC);
+        data.extend_from_slice(&SAVE_VERSION.to_le_bytes());
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.push(self.generation);
+
+        // Every material is listed by name, and its position in this list is used as its id below
+        write_varint(&mut data, CELL_TYPES.len() as u64);
+        for cell_type in CELL_TYPES.iter() {
+            let name = &CellType::get_properties(*cell_type).name;
+            write_varint(&mut data, name.len() as u64);
+            data.extend_from_slice(name.as_bytes());
+        }
+
+        // Write out the cells, combining each run of identical cells into one record
+        let mut start = 0;
+        while start < self.cells.len() {
+            let record = SavedCell::from_cell(&self.cells[start]);
+            let mut end = start + 1;
+            while end < self.cells.len() && SavedCell::from_cell(&self.cells[end]) == record {
+                end += 1;
+            }
+            write_varint(&mut data, (end - start) as u64);
+            record.write(&mut data);
+            start = end;
+        }
+        data
+    }
+
+    /// Loads a space that was saved with Space::save() (by this version of the program or an older one)
+    /// Cells of materials that don't exist in this version are left empty (and a message is logged saying so)
+    pub fn load(data: &[u8]) -> Result<Space, LoadError> {
+        let mut reader = SaveReader { data, position: 0 };
+        if reader.read_bytes(SAVE_MAGIC.len())? != SAVE_MAGIC {
+            return Err(LoadError::new("this isn't a saved space"));
+        }
+        let version = u16::from_le_bytes(reader.read_bytes(2)?.try_into().unwrap());
+        if version == 0 || version > SAVE_VERSION {
+            return Err(LoadError::new(&format!("this space was saved in version {} of the format, but only versions up to {} can be loaded", version, SAVE_VERSION)));
+        }
+        let width = u32::from_le_bytes(reader.read_bytes(4)?.try_into().unwrap());
+        let height = u32::from_le_bytes(reader.read_bytes(4)?.try_into().unwrap());
+        let generation = reader.read_bytes(1)?[0];
+        if width == 0 || height == 0 || width as u64 * height as u64 > MAX_LOADED_CELLS {
+            return Err(LoadError::new(&format!("the saved space has an unsupported size ({} by {})", width, height)));
+        }
+
+        // Look up each of the saved materials by name (None means there's no material with that name)
+        let mut materials = vec![];
+        let mut unknown = vec![];
+        for _ in 0..reader.read_varint()? {
+            let length = reader.read_varint()? as usize;
+            let name = String::from_utf8_lossy(reader.read_bytes(length)?).into_owned();
+            let cell_type = CellType::from_name(&name);
+            if cell_type.is_none() {
+                unknown.push(name);
+            }
+            materials.push(cell_type);
+        }
+        let material = |id: u64| materials.get(id as usize).copied().ok_or_else(|| LoadError::new("a saved cell has a material that isn't in the list of materials"));
+
+        // Read all the runs first, and check that they fill the space exactly, before making the space
+        // That way a damaged header or a save that's been cut short is found out here, before the memory for all
+        // the cells is allocated
+        let total = width as u64 * height as u64;
+        let mut runs = vec![];
+        let mut covered = 0;
+        let mut unknown_cells = 0;
+        while covered < total {
+            let length = reader.read_varint()?;
+            let record = SavedCell::read(&mut reader)?;
+            if length == 0 || length > total - covered {
+                return Err(LoadError::new("the saved cells don't fit in the space"));
+            }
+
+            // A cell of an unknown material is replaced with a fresh empty cell, rather than keeping the temperature
+            // and lifetime it had as that material
+            let mut cell = Cell::empty();
+            match material(record.material)? {
+                Some(cell_type) => {
+                    cell.cell_type = cell_type;
+                    cell.temp = record.temp as f32;
+                    cell.lifetime = record.lifetime;
+                    cell.emits = material(record.emits)?.unwrap_or(CellType::Empty);
+                },
+                None => unknown_cells += length,
+            }
+            cell.generation = generation;
+
+            covered += length;
+            runs.push((length, cell));
+        }
+
+        let mut space = Space::new(width, height);
+        space.generation = generation;
+        let mut i = 0;
+        for (length, cell) in runs {
+            for _ in 0..length {
+                space.cells[i] = cell;
+                i += 1;
+            }
+        }
+
+        if !unknown.is_empty() {
+            log(&format!("Left {} cells empty because they were made of unknown materials: {}", unknown_cells, unknown.join(", ")));
+        }
+        Ok(space)
+    }
+
+    /// Saves the space like save() does, and then compresses it
+    pub fn save_compressed(&self) -> Vec<u8> {
+        miniz_oxide::deflate::compress_to_vec(&self.save(), COMPRESSION_LEVEL)
+    }
+
+    /// Loads a space that was saved with Space::save_compressed()
+    pub fn load_compressed(data: &[u8]) -> Result<Space, LoadError> {
+        let data = miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_DECOMPRESSED_SIZE)
+            .map_err(|_| LoadError::new("the saved space couldn't be decompressed"))?;
+        Space::load(&data)
+    }
+
     /// Sets a cell at the specified index to be a copy of the provided cell
     pub fn set_cell(&mut self, i: usize, cell: &Cell) {
         // Copy the cell to the specified index
@@ -152,3 +374,212 @@ impl Space {
     }
 }
 
+
+/// The parts of a cell that are saved, which is what decides whether two cells can share a run in a saved space
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct SavedCell {
+    /// The cell's material, as a position in the list of materials in the save
+    material: u64,
+    /// The cell's temperature, rounded to a whole degree
+    temp: i64,
+    /// The number of ticks left before the cell dissipates (or 0 if it lasts forever)
+    lifetime: u16,
+    /// The material the cell emits (for clones and taps), as a position in the list of materials
+    emits: u64,
+}
+
+impl SavedCell {
+    /// Gets the parts of the given cell that are saved
+    /// Materials are written as their position in CELL_TYPES, which is the order they're listed in by Space::save()
+    fn from_cell(cell: &Cell) -> SavedCell {
+        SavedCell {
+            material: cell.cell_type as u64,
+            temp: cell.temp.round() as i64,
+            lifetime: cell.lifetime,
+            emits: cell.emits as u64,
+        }
+    }
+
+    /// Writes this cell out (after the length of its run)
+    fn write(&self, data: &mut Vec<u8>) {
+        write_varint(data, self.material);
+        write_varint(data, zigzag_encode(self.temp));
+        write_varint(data, self.lifetime as u64);
+        write_varint(data, self.emits);
+    }
+
+    /// Reads a cell in (after the length of its run)
+    fn read(reader: &mut SaveReader) -> Result<SavedCell, LoadError> {
+        let material = reader.read_varint()?;
+        let temp = reader.read_varint()?;
+        let lifetime = reader.read_varint()?;
+        let emits = reader.read_varint()?;
+        Ok(SavedCell {
+            material,
+            temp: zigzag_decode(temp),
+            lifetime: lifetime.min(u16::MAX as u64) as u16,
+            emits,
+        })
+    }
+}
+
+/// Writes a number as a variable-length integer (7 bits at a time, with the top bit set if more bytes follow)
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+/// Zigzag-encodes a number, so that small negative numbers become small positive ones (0, -1, 1, -2, 2...
+/// become 0, 1, 2, 3, 4...), which then only take a byte or two as a varint
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Undoes zigzag_encode()
+fn zigzag_decode(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Reads the parts of a saved space one at a time, keeping track of how far it's got
+struct SaveReader<'a> {
+    /// The saved space
+    data: &'a [u8],
+    /// The position of the next byte to read
+    position: usize,
+}
+
+impl<'a> SaveReader<'a> {
+    /// Reads the given number of bytes, or returns an error if the data ends first
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], LoadError> {
+        if length > self.data.len() - self.position {
+            return Err(LoadError::new("the saved space is cut short"));
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    /// Reads a number written by write_varint()
+    fn read_varint(&mut self) -> Result<u64, LoadError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_bytes(1)?[0];
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(LoadError::new("the saved space contains a number that's too large"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::SeededRandom;
+
+    /// Makes a small space with a few different kinds of cells in it
+    fn example_space() -> Space {
+        let mut space = Space::new(7, 5);
+        let mut rng = SeededRandom::new(2);
+        for (x, y, cell_type) in [(0, 0, CellType::Sand), (1, 0, CellType::Sand), (3, 1, CellType::Steam), (6, 4, CellType::Ice), (2, 3, CellType::Tap)] {
+            let i = space.get_index(x, y);
+            space.cells[i].init(cell_type, &mut rng);
+        }
+        let i = space.get_index(2, 3);
+        space.cells[i].emits = CellType::Water;
+        space.increment_generation();
+        space
+    }
+
+    /// Checks that the loaded space has everything that's saved from the original
+    fn assert_same(loaded: &Space, original: &Space) {
+        assert_eq!((loaded.width, loaded.height, loaded.generation), (original.width, original.height, original.generation));
+        for (loaded, original) in loaded.cells.iter().zip(original.cells.iter()) {
+            assert_eq!(SavedCell::from_cell(loaded), SavedCell::from_cell(original));
+        }
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let space = example_space();
+        assert_same(&Space::load(&space.save()).unwrap(), &space);
+        assert_same(&Space::load_compressed(&space.save_compressed()).unwrap(), &space);
+    }
+
+    #[test]
+    fn rejects_saves_that_are_cut_short() {
+        let data = example_space().save();
+        for length in 0..data.len() {
+            assert!(Space::load(&data[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_huge_spaces_without_the_cells_for_them() {
+        // A header claiming a space far bigger than the limit
+        let mut data = SAVE_MAGIC.to_vec();
+        data.extend_from_slice(&SAVE_VERSION.to_le_bytes());
+        data.extend_from_slice(&8192u32.to_le_bytes());
+        data.extend_from_slice(&8192u32.to_le_bytes());
+        data.extend_from_slice(&[0, 0]);
+        assert!(Space::load(&data).is_err());
+
+        // A space within the limit, but with only enough runs to fill a 7 by 5 space
+        let mut data = example_space().save();
+        data[6..10].copy_from_slice(&2000u32.to_le_bytes());
+        data[10..14].copy_from_slice(&2000u32.to_le_bytes());
+        assert!(Space::load(&data).is_err());
+    }
+
+    #[test]
+    fn unknown_materials_load_as_empty_space() {
+        // Rename steam in the list of materials, as if it's been taken out of the game since the space was saved
+        let space = example_space();
+        let mut data = space.save();
+        let name = data.windows(5).position(|bytes| bytes == b"Steam").unwrap();
+        data[name..name + 5].copy_from_slice(b"Stean");
+
+        // The steam is replaced by a fresh empty cell (without its temperature or lifetime), and nothing else changes
+        let loaded = Space::load(&data).unwrap();
+        let mut empty = Cell::empty();
+        empty.generation = space.generation;
+        let steam = space.get_index(3, 1);
+        assert_eq!(loaded.cells[steam], empty);
+        for (i, (loaded, original)) in loaded.cells.iter().zip(space.cells.iter()).enumerate() {
+            if i != steam {
+                assert_eq!(SavedCell::from_cell(loaded), SavedCell::from_cell(original));
+            }
+        }
+    }
+
+    #[test]
+    fn varints() {
+        for (value, length) in [(0, 1), (1, 1), (127, 1), (128, 2), (300, 2), (u32::MAX as u64, 5), (u64::MAX, 10)] {
+            let mut data = vec![];
+            write_varint(&mut data, value);
+            assert_eq!(data.len(), length);
+            let mut reader = SaveReader { data: &data, position: 0 };
+            assert_eq!(reader.read_varint(), Ok(value));
+        }
+
+        // A varint that never ends, or that's cut short
+        assert!(SaveReader { data: &[0x80; 11], position: 0 }.read_varint().is_err());
+        assert!(SaveReader { data: &[0x80, 0x80], position: 0 }.read_varint().is_err());
+    }
+
+    #[test]
+    fn zigzag() {
+        for (value, encoded) in [(0, 0), (-1, 1), (1, 2), (-2, 3), (2, 4), (-100, 199)] {
+            assert_eq!(zigzag_encode(value), encoded);
+            assert_eq!(zigzag_decode(encoded), value);
+        }
+        for value in [i64::MIN, i64::MAX] {
+            assert_eq!(zigzag_decode(zigzag_encode(value)), value);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! This file keeps spaces in the browser's local storage, so that they aren't lost when the page is closed.
+//! The space is saved automatically every so often (and when the page is closed), and that autosave is
+//! loaded again the next time the page is opened.  Spaces can also be saved into named slots, to be
+//! loaded again later.
+//!
+//! Local storage can only hold text, so each space is compressed (see Space::save_compressed()) and then
+//! written out in base64.  Browsers only give each page a few megabytes of storage, so saving can fail
+//! if there are too many slots, in which case a message is logged and the old save is kept.
+//!
+//! A space can also be shared as a link, by putting it in the fragment of the page's address (the part
+//! after the `#`), in the form `#space=...`.  It's compressed in the same way, but written in the URL-safe
+//! form of base64 (which uses `-` and `_` instead of `+` and `/`).  This works best for small scenes, since
+//! some browsers and chat programs can't handle very long links.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::Storage;
+use base64::Engine;
+use base64::engine::general_purpose::{ STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL };
+
+use crate::world::World;
+use crate::space::{ Space, LoadError };
+use crate::log;
+
+/// The key that the autosave is kept under
+const AUTOSAVE_KEY: &str = "fallingrust-autosave";
+/// The start of the keys that the named slots are kept under (followed by the name of the slot)
+const SLOT_PREFIX: &str = "fallingrust-slot-";
+/// How often the space is saved automatically (in milliseconds)
+const AUTOSAVE_INTERVAL: i32 = 10000;
+/// The start of the fragment of a link that has a space in it (followed by the space)
+const FRAGMENT_PREFIX: &str = "#space=";
+
+/// Gets the browser's local storage, which can be missing (eg. if the user has turned it off)
+fn local_storage() -> Result<Storage, String> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| "the browser's local storage isn't available".to_string())
+}
+
+/// Saves the space under the given key, replacing whatever was saved there before
+fn store(key: &str, space: &Space) -> Result<(), String> {
+    let text = BASE64.encode(space.save_compressed());
+    local_storage()?
+        .set_item(key, &text)
+        .map_err(|_| "there isn't enough room left in the browser's local storage".to_string())
+}
+
+/// Loads the space saved under the given key, or returns None if nothing has been saved there
+fn restore(key: &str) -> Result<Option<Space>, LoadError> {
+    let text = match local_storage().ok().and_then(|storage| storage.get_item(key).ok().flatten()) {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    let data = BASE64.decode(text).map_err(|_| LoadError { message: "the saved space has been damaged".to_string() })?;
+    Space::load_compressed(&data).map(Some)
+}
+
+/// Loads the last autosave into the world (if there is one, and restore_last is true), and then starts saving
+/// the world automatically every so often, and whenever the page is closed
+pub fn start_autosave(world: Rc<RefCell<World>>, restore_last: bool) {
+    let last = if restore_last { restore(AUTOSAVE_KEY) } else { Ok(None) };
+    match last {
+        Ok(Some(space)) => {
+            world.borrow_mut().set_space(space);
+            log("Restored the space from the last autosave");
+        },
+        Ok(None) => { },
+        // A broken autosave is just skipped (it'll be replaced by the next one)
+        Err(err) => log(&format!("Couldn't restore the last autosave: {}", err)),
+    }
+
+    let autosave = Closure::wrap(Box::new(move || {
+        if let Err(err) = store(AUTOSAVE_KEY, &world.borrow().space) {
+            log(&format!("Couldn't autosave the space: {}", err));
+        }
+    }) as Box<dyn FnMut()>);
+
+    let window = web_sys::window().unwrap();
+    window.set_interval_with_callback_and_timeout_and_arguments_0(
+        autosave.as_ref().unchecked_ref(),
+        AUTOSAVE_INTERVAL
+    ).unwrap();
+    window.set_onpagehide(Some(autosave.as_ref().unchecked_ref()));
+
+    // Leaks memory! But necessary for the callback to remain valid
+    autosave.forget();
+}
+
+/// Makes a link to the current page with the given space in its fragment, so the space can be shared
+pub fn share_link(space: &Space) -> String {
+    let href = web_sys::window().unwrap().location().href().unwrap_or_default();
+    let page = href.split('#').next().unwrap_or("");
+    format!("{}{}{}", page, FRAGMENT_PREFIX, BASE64_URL.encode(space.save_compressed()))
+}
+
+/// Loads the space in the fragment of the page's address (the part after the `#`), which is how spaces
+/// are shared in links.  Returns None if there isn't a space in the fragment
+/// The fragment is then removed from the address (without reloading the page), so that reloading the page
+/// carries on from the autosave instead of going back to the shared space
+pub fn load_shared_link() -> Option<Result<Space, LoadError>> {
+    let window = web_sys::window()?;
+    let hash = window.location().hash().ok()?;
+    let text = hash.strip_prefix(FRAGMENT_PREFIX)?;
+
+    let space = BASE64_URL.decode(text)
+        .map_err(|_| LoadError { message: "the space in the link has been damaged (it may have been cut short)".to_string() })
+        .and_then(|data| Space::load_compressed(&data));
+
+    let href = window.location().href().unwrap_or_default();
+    if let Ok(history) = window.history() {
+        let _ = history.replace_state_with_url(&JsValue::NULL, "", Some(href.split('#').next().unwrap_or("")));
+    }
+    Some(space)
+}
+
+/// Saves the world's space into the slot with the given name, replacing whatever was saved there before
+pub fn save_slot(world: &World, name: &str) -> Result<(), String> {
+    store(&format!("{}{}", SLOT_PREFIX, name), &world.space)
+}
+
+/// Loads the space saved in the slot with the given name into the world
+/// Returns false (and leaves the world as it was) if nothing has been saved in that slot
+pub fn load_slot(world: &mut World, name: &str) -> Result<bool, LoadError> {
+    match restore(&format!("{}{}", SLOT_PREFIX, name))? {
+        Some(space) => {
+            world.set_space(space);
+            Ok(true)
+        },
+        None => Ok(false),
+    }
+}
+
+/// Deletes the space saved in the slot with the given name (if there is one)
+pub fn delete_slot(name: &str) {
+    if let Ok(storage) = local_storage() {
+        let _ = storage.remove_item(&format!("{}{}", SLOT_PREFIX, name));
+    }
+}
+
+/// Returns the names of all the slots that have a space saved in them, in alphabetical order
+pub fn list_slots() -> Vec<String> {
+    let storage = match local_storage() {
+        Ok(storage) => storage,
+        Err(_) => return vec![],
+    };
+
+    let mut names: Vec<String> = (0..storage.length().unwrap_or(0))
+        .filter_map(|i| storage.key(i).ok().flatten())
+        .filter_map(|key| key.strip_prefix(SLOT_PREFIX).map(|name| name.to_string()))
+        .collect();
+    names.sort();
+    names
+}
@@ -32,11 +32,23 @@ impl Timer {
 
     /// Gets the current time in milliseconds
     /// Uses the browser's high-precision performance.now() method
+    #[cfg(all(feature = "web", target_arch = "wasm32"))]
     pub fn now() -> u32 {
         // Access the browser's window object
         let performance = web_sys::window().unwrap().performance().unwrap();
         // Get the current time in milliseconds and convert it to a u32
         performance.now() as u32
     }
-}
 
+    /// Gets the current time in milliseconds
+    /// When running natively, this is measured from the first time the timer is used
+    #[cfg(not(all(feature = "web", target_arch = "wasm32")))]
+    pub fn now() -> u32 {
+        use std::time::Instant;
+
+        thread_local! {
+            static EPOCH: Instant = Instant::now();
+        }
+        EPOCH.with(|epoch| epoch.elapsed().as_millis() as u32)
+    }
+}
@@ -8,12 +8,14 @@ use std::cell::RefCell;
 // Import WebAssembly and browser-related modules
 use wasm_bindgen::prelude::*;           // For JavaScript interop
 use wasm_bindgen::JsCast;               // For type casting between JavaScript and Rust types
-use web_sys::{ Document, Window, HtmlElement, HtmlCanvasElement, CanvasRenderingContext2d, MouseEvent, TouchEvent };
+use web_sys::{ Document, Window, HtmlElement, HtmlCanvasElement, CanvasRenderingContext2d, MouseEvent, KeyboardEvent, TouchEvent };
 
 // Import our game modules
-use crate::world::World;
+use crate::world::{ World, CUSTOM_RULES };
 use crate::space::Space;
 use crate::cells::CellType;
+use crate::forces::ForceField;
+use crate::simulator::SIMULATORS;
 use crate::{ REFRESH, CELL_WIDTH, CELL_HEIGHT }; // Constants from lib.rs
 
 // These imports are marked with #[allow(unused_imports)] to silence warnings
@@ -23,6 +25,9 @@ use crate::timer::Timer;
 #[allow(unused_imports)]
 use crate::{ alert, log};
 
+/// The colour that cells carrying a spark of electricity are drawn in
+const SPARK_COLOUR: &str = "#FFFF66";
+
 /// Main function to initialize the DOM and set up all event handlers
 /// Takes a reference-counted, refcell-wrapped World instance that will be shared
 /// among event handlers
@@ -43,8 +48,10 @@ pub fn init_dom(world: Rc<RefCell<World>>) {
 
     // Initialize all event handlers
     init_button_events(&document, world.clone());  // UI buttons (play/pause, cell type selection)
+    init_simulator_buttons(&document, world.clone()); // Buttons for choosing the simulator
     init_mouse_events(&canvas, world.clone());     // Mouse input on canvas
     init_touch_events(&canvas, world.clone());     // Touch input for mobile devices
+    init_keyboard_events(&document, world.clone()); // Keyboard shortcuts (undo and redo)
     init_draw_events(&window, canvas, world.clone()); // Animation loop for rendering
 }
 
@@ -58,32 +65,124 @@ fn init_button_events(document: &Document, world: Rc<RefCell<World>>) {
         });
     }
 
+    // Set up the undo and redo buttons
+    {
+        let world = world.clone();
+        register_click(document, "undo", move || {
+            world.borrow_mut().undo();
+        });
+    }
+    {
+        let world = world.clone();
+        register_click(document, "redo", move || {
+            world.borrow_mut().redo();
+        });
+    }
+
     // Get the container for cell type selection buttons
     let container = document
         .get_element_by_id("select-types").unwrap()
         .dyn_into::<HtmlElement>().unwrap();
 
     // Create a button for erasing (placing Empty cells)
-    create_select_button(&document, &container, world.clone(), "Erase", CellType::Empty);
+    create_select_button(document, &container, world.clone(), "Erase", CellType::Empty);
     
     // Create buttons for each cell type
     for cell_type in CellType::iter() {
         let props = CellType::get_properties(*cell_type);
-        create_select_button(&document, &container, world.clone(), props.name, *cell_type);
+        create_select_button(document, &container, world.clone(), &props.name, *cell_type);
     }
+
+    // Get the container for the buttons that change the forces
+    let container = document
+        .get_element_by_id("forces").unwrap()
+        .dyn_into::<HtmlElement>().unwrap();
+
+    // Create buttons for changing gravity and the wind everywhere
+    create_force_button(document, &container, world.clone(), "Reverse Gravity", |forces| forces.reverse_gravity());
+    create_force_button(document, &container, world.clone(), "Rotate Gravity", |forces| forces.rotate_gravity(90.0));
+    create_force_button(document, &container, world.clone(), "Wind Left", |forces| forces.wind.0 -= 0.25);
+    create_force_button(document, &container, world.clone(), "Calm", |forces| forces.wind = (0.0, 0.0));
+    create_force_button(document, &container, world.clone(), "Wind Right", |forces| forces.wind.0 += 0.25);
+
+    // Create buttons for making the air colder (eg. below freezing, so water freezes) or warmer
+    create_force_button(document, &container, world.clone(), "Colder", |forces| forces.ambient_temp -= 10.0);
+    create_force_button(document, &container, world.clone(), "Warmer", |forces| forces.ambient_temp += 10.0);
+
+    // Create buttons for painting forces onto parts of the space with the mouse
+    create_paint_button(document, &container, world.clone(), "Paint Wind Left", (-1.0, 0.0));
+    create_paint_button(document, &container, world.clone(), "Paint Wind Right", (1.0, 0.0));
+    create_paint_button(document, &container, world.clone(), "Paint Updraft", (0.0, -1.0));
+    create_force_button(document, &container, world, "Clear Painted", |forces| forces.clear_painted());
+}
+
+// The buttons for choosing the simulator are kept here, so that they can be updated from lib.rs when JavaScript
+// changes the simulator (see set_simulator() and load_rules() there)
+thread_local! {
+    static SIMULATOR_BUTTONS: RefCell<Vec<(&'static str, HtmlElement)>> = const { RefCell::new(vec![]) };
+}
+
+/// Creates the buttons for choosing the simulator, and marks the one being used
+/// The button for the custom rules only appears once some have been loaded
+fn init_simulator_buttons(document: &Document, world: Rc<RefCell<World>>) {
+    // Get the container for simulator selection buttons
+    let container = document
+        .get_element_by_id("select-simulator").unwrap()
+        .dyn_into::<HtmlElement>().unwrap();
+
+    // Create a button for each of the simulators, and one for the custom rules
+    let names = SIMULATORS.iter().map(|(name, _)| *name).chain(Some(CUSTOM_RULES));
+    let buttons: Vec<(&str, HtmlElement)> = names.map(|name| {
+        let world = world.clone();
+        let button = create_button(document, name, move || {
+            world.borrow_mut().select_simulator(name);
+            update_simulator_buttons(&world.borrow());
+        });
+        container.append_child(&button).unwrap();
+        (name, button)
+    }).collect();
+
+    SIMULATOR_BUTTONS.with(|global| *global.borrow_mut() = buttons);
+    update_simulator_buttons(&world.borrow());
+}
+
+/// Marks the button for the simulator that the world is using, and shows the button for the custom rules
+/// if some have been loaded.  This needs calling whenever either of those changes
+pub fn update_simulator_buttons(world: &World) {
+    let (simulator, custom) = (world.get_simulator_name(), world.has_custom_rules());
+    SIMULATOR_BUTTONS.with(|global| {
+        for (name, button) in global.borrow().iter() {
+            button.set_class_name(if *name == simulator { "active" } else { "" });
+            button.set_hidden(*name == CUSTOM_RULES && !custom);
+        }
+    });
+}
+
+/// Creates a button that changes the forces acting on the world using the given function
+fn create_force_button(document: &Document, container: &HtmlElement, world: Rc<RefCell<World>>, name: &str, f: fn(&mut ForceField)) {
+    container.append_child(&create_button(document, name, move || {
+        f(&mut world.borrow_mut().forces);
+    })).unwrap();
+}
+
+/// Creates a button for painting a force (instead of placing cells) with the mouse
+fn create_paint_button(document: &Document, container: &HtmlElement, world: Rc<RefCell<World>>, name: &str, force: (f32, f32)) {
+    container.append_child(&create_button(document, name, move || {
+        world.borrow_mut().input.update_selected_force(force);
+    })).unwrap();
 }
 
 /// Creates a button for selecting a specific cell type
 fn create_select_button(document: &Document, container: &HtmlElement, world: Rc<RefCell<World>>, name: &str, cell_type: CellType) {
     // Create a button with the given name that updates the selected cell type when clicked
-    container.append_child(&create_button(&document, name, move || {
+    container.append_child(&create_button(document, name, move || {
         world.borrow_mut().input.update_selected_type(cell_type);
     })).unwrap();
 }
 
 /// Generic function to create a button element with the given name and click handler
 fn create_button<F>(document: &Document, name: &str, f: F) -> HtmlElement 
-    where F: 'static + Fn() -> () { // Function type constraint: must be static and take no arguments
+    where F: 'static + Fn() { // Function type constraint: must be static and take no arguments
     // Wrap the Rust closure in a form that can be called from JavaScript
     let cb = Closure::wrap(Box::new(f) as Box<dyn FnMut()>);
 
@@ -104,7 +203,7 @@ fn create_button<F>(document: &Document, name: &str, f: F) -> HtmlElement
 
 /// Registers a click event handler for an element with the given ID
 fn register_click<F>(document: &Document, element_id: &str, f: F) 
-    where F: 'static + Fn() -> () {
+    where F: 'static + Fn() {
     // Wrap the Rust closure in a form that can be called from JavaScript
     let cb = Closure::wrap(Box::new(f) as Box<dyn FnMut()>);
 
@@ -205,6 +304,35 @@ fn init_touch_events(canvas: &HtmlCanvasElement, world: Rc<RefCell<World>>) {
     }
 }
 
+/// Sets up the keyboard shortcuts: Ctrl+Z to undo, and Ctrl+Shift+Z or Ctrl+Y to redo
+/// (or Cmd instead of Ctrl on a Mac)
+fn init_keyboard_events(document: &Document, world: Rc<RefCell<World>>) {
+    let cb = Closure::wrap(Box::new(move |e: KeyboardEvent| {
+        if !(e.ctrl_key() || e.meta_key()) {
+            return;
+        }
+        // Leave the text boxes (eg. the slot name) to do their own undo and redo
+        let typing = e.target()
+            .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+            .map(|element| element.tag_name() == "INPUT")
+            .unwrap_or(false);
+        if typing {
+            return;
+        }
+        match e.key().to_lowercase().as_str() {
+            "z" if e.shift_key() => { world.borrow_mut().redo(); },
+            "z" => { world.borrow_mut().undo(); },
+            "y" => { world.borrow_mut().redo(); },
+            _ => return,
+        }
+        // Stop the browser from doing its own undo or redo as well
+        e.prevent_default();
+    }) as Box<dyn FnMut(KeyboardEvent)>);
+
+    document.set_onkeydown(Some(cb.as_ref().unchecked_ref()));
+    cb.forget();
+}
+
 /// Sets up the animation loop and rendering
 fn init_draw_events(window: &Window, canvas: HtmlCanvasElement, world: Rc<RefCell<World>>) { 
     let mut drawing = false;  // Flag to prevent overlapping draw operations
@@ -247,6 +375,12 @@ fn draw_canvas(canvas: &HtmlCanvasElement, space: &Space) {
         .get_context("2d").unwrap().unwrap()
         .dyn_into::<CanvasRenderingContext2d>().unwrap();
 
+    // A loaded space can be a different size, so make sure the canvas fits the space
+    if canvas.width() != space.get_width() * CELL_WIDTH || canvas.height() != space.get_height() * CELL_HEIGHT {
+        canvas.set_width(space.get_width() * CELL_WIDTH);
+        canvas.set_height(space.get_height() * CELL_HEIGHT);
+    }
+
     // Clear the canvas
     context.clear_rect(0.0, 0.0, 
         (space.get_width() * CELL_WIDTH) as f64, 
@@ -256,7 +390,7 @@ fn draw_canvas(canvas: &HtmlCanvasElement, space: &Space) {
     // Rather than changing color for each cell, we draw all cells of the same type at once
     for draw_type in CellType::iter() {
         // Set the fill color for this cell type
-        context.set_fill_style(&JsValue::from(cell_type_to_colour(*draw_type)));
+        context.set_fill_style_str(&cell_type_to_colour(*draw_type));
         context.begin_path();
         
         // Find all cells of this type and add them to the current path
@@ -276,22 +410,27 @@ fn draw_canvas(canvas: &HtmlCanvasElement, space: &Space) {
         // Fill all cells of this type at once
         context.fill();
     }
-}
 
-/// Converts a cell type to a CSS color string
-fn cell_type_to_colour(cell_type: CellType) -> &'static str {
-    match cell_type {
-        CellType::Empty => "#FFFFFF",      // White
-        CellType::Rock => "#000000",       // Black
-        CellType::Wood => "#606040",       // Dark olive
-        CellType::Sand => "#886611",       // Brown
-        CellType::Gunpowder => "#666666",  // Dark gray
-        CellType::Water => "#0000FF",      // Blue
-        CellType::Oil => "#007777",        // Teal
-        CellType::Propane => "#77FFFF",    // Light cyan
-        CellType::Fire => "#FF3300",       // Orange-red
-        CellType::Lava => "#993300",       // Dark orange
-        CellType::Acid => "#009966",       // Green
+    // Draw the sparks on top, so that electricity can be seen travelling along the wires
+    context.set_fill_style_str(SPARK_COLOUR);
+    context.begin_path();
+    for y in 0..space.get_height() {
+        for x in 0..space.get_width() {
+            if space.get_cell(x, y).is_sparking() {
+                context.rect(
+                    (x * CELL_WIDTH) as f64,
+                    (y * CELL_HEIGHT) as f64,
+                    CELL_WIDTH as f64,
+                    CELL_HEIGHT as f64
+                );
+            }
+        }
     }
+    context.fill();
 }
 
+/// Converts a cell type to a CSS color string, using the colour given to it in materials.txt
+fn cell_type_to_colour(cell_type: CellType) -> String {
+    let (r, g, b) = CellType::get_properties(cell_type).colour;
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
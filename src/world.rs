@@ -3,11 +3,26 @@
 
 // Import the components we need for our world
 /// The grid where cells live
-use crate::space::Space;
+use crate::space::{ Space, LoadError };
 /// Tracks user input
 use crate::input::InputTracker;
 /// Simulation algorithms
-use crate::simulator::{ Simulator, SwappingSim, CellularSim };
+use crate::simulator::{ Simulator, CellularSim, SIMULATORS };
+/// Errors in custom rules
+use crate::rules::RuleError;
+/// Converting spaces to and from images
+use crate::image;
+/// Random number generation
+use crate::random::{ Random, SeededRandom };
+/// Gravity and wind
+use crate::forces::ForceField;
+/// The user's changes, which can be undone
+use crate::history::History;
+/// Picks a seed when one isn't given
+use crate::random_seed;
+
+/// The name of the simulator that uses the custom rules loaded with World::load_rules()
+pub const CUSTOM_RULES: &str = "Custom Rules";
 
 /// The World struct is the main container for our simulation
 /// It coordinates all the different parts and represents the entire game state
@@ -18,20 +33,164 @@ pub struct World {
     pub space: Space,
     /// Tracks user mouse input
     pub input: InputTracker,
+    /// The gravity and wind that push the cells around
+    pub forces: ForceField,
     /// The simulation algorithm to use (boxed trait object)
     simulator: Box<dyn Simulator>,
+    /// The name of the simulation algorithm being used
+    simulator_name: &'static str,
+    /// The text of the last custom rules that were loaded (if any), so they can be chosen again after
+    /// switching to one of the other simulators
+    custom_rules: Option<String>,
+    /// The seed the random number generator was started with
+    seed: u64,
+    /// The source of all random decisions made by the simulation
+    rng: Box<dyn Random>,
+    /// The brush strokes the user has made, so they can be undone and redone
+    history: History,
 }
 
 impl World {
-    /// Creates a new world with the given dimensions
+    /// Creates a new world with the given dimensions, using a randomly chosen seed
     pub fn new(width: u32, height: u32) -> World {
+        World::with_seed(width, height, random_seed())
+    }
+
+    /// Creates a new world with the given dimensions and random seed
+    /// Two worlds created with the same seed, and given the same inputs, will always produce the same cells
+    pub fn with_seed(width: u32, height: u32, seed: u64) -> World {
         World {
             run: true,                        // Start with the simulation running
             space: Space::new(width, height), // Create a new empty space with the given dimensions
             input: InputTracker::new(),       // Initialize input tracking
-            simulator: Box::new(SwappingSim { }), // Use the SwappingSim algorithm
-            //simulator: Box::new(CellularSim { }), // Alternative simulator (commented out)
+            forces: ForceField::new(width, height), // Normal gravity and no wind
+            simulator: (SIMULATORS[0].1)(),   // Use the default simulation algorithm
+            simulator_name: SIMULATORS[0].0,
+            custom_rules: None,
+            seed,
+            rng: Box::new(SeededRandom::new(seed)),
+            history: History::new(),
+        }
+    }
+
+    /// Returns the seed that the random number generator was started with
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Replaces the random number generator with a different source
+    /// The seed reported by get_seed() is whatever the given generator was started with
+    pub fn set_random(&mut self, seed: u64, rng: Box<dyn Random>) {
+        self.seed = seed;
+        self.rng = rng;
+    }
+
+    /// Switches to the simulator with the given name (from the SIMULATORS list in simulator.rs, or CUSTOM_RULES
+    /// once some custom rules have been loaded)
+    /// All the cells are kept, so the new simulator carries on from where the old one left off
+    /// Returns false (and keeps the current simulator) if there's no simulator with that name
+    pub fn select_simulator(&mut self, name: &str) -> bool {
+        if name == CUSTOM_RULES {
+            // The rules were checked when they were loaded, so they can always be read in again
+            return match self.custom_rules.as_deref().map(CellularSim::with_rules) {
+                Some(Ok(simulator)) => {
+                    self.set_simulator(CUSTOM_RULES, Box::new(simulator));
+                    true
+                },
+                _ => false,
+            };
         }
+
+        match SIMULATORS.iter().find(|(simulator_name, _)| *simulator_name == name) {
+            Some((simulator_name, create)) => {
+                self.set_simulator(simulator_name, create());
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Switches to the given simulator, which doesn't have to be one from the SIMULATORS list
+    /// All the cells are kept, so the new simulator carries on from where the old one left off
+    pub fn set_simulator(&mut self, name: &'static str, simulator: Box<dyn Simulator>) {
+        self.simulator = simulator;
+        self.simulator_name = name;
+    }
+
+    /// Returns the name of the simulator being used
+    pub fn get_simulator_name(&self) -> &'static str {
+        self.simulator_name
+    }
+
+    /// Switches to the cellular automaton simulator, using the given rules instead of the built-in ones (see
+    /// rules.rs for how to write them).  The rules are kept, so they can be chosen again with CUSTOM_RULES
+    /// after switching to another simulator, until another set of rules is loaded
+    /// Returns an error (and keeps the current simulator) if the rules can't be read
+    pub fn load_rules(&mut self, text: &str) -> Result<(), RuleError> {
+        let simulator = CellularSim::with_rules(text)?;
+        self.set_simulator(CUSTOM_RULES, Box::new(simulator));
+        self.custom_rules = Some(text.to_string());
+        Ok(())
+    }
+
+    /// Checks if any custom rules have been loaded (so that CUSTOM_RULES can be chosen)
+    pub fn has_custom_rules(&self) -> bool {
+        self.custom_rules.is_some()
+    }
+
+    /// Saves all the cells in the world (see Space::save() for the format)
+    pub fn save(&self) -> Vec<u8> {
+        self.space.save()
+    }
+
+    /// Replaces all the cells in the world with ones saved by save()
+    /// The saved space can be a different size, in which case any painted forces are cleared (but gravity and
+    /// the wind stay the same).  If the save can't be loaded, the world is left as it was
+    pub fn load(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        let space = Space::load(data)?;
+        self.set_space(space);
+        Ok(())
+    }
+
+    /// Draws all the cells in the world into a PNG image (see image.rs)
+    pub fn save_png(&self) -> Vec<u8> {
+        image::save_png(&self.space)
+    }
+
+    /// Replaces all the cells in the world with ones read from a PNG image (see image.rs)
+    /// Like load(), the image can be a different size, and the world is left as it was if the image can't be read
+    pub fn load_png(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        let space = image::load_png(data, self.rng.as_mut())?;
+        self.set_space(space);
+        Ok(())
+    }
+
+    /// Replaces the space with a different one (eg. one that was just loaded), resizing the forces to fit it
+    /// if it's a different size.  Gravity, the wind and the temperature of the air stay the same, but any painted
+    /// forces are cleared
+    pub fn set_space(&mut self, space: Space) {
+        if space.get_width() != self.space.get_width() || space.get_height() != self.space.get_height() {
+            let mut forces = ForceField::new(space.get_width(), space.get_height());
+            forces.gravity = self.forces.gravity;
+            forces.wind = self.forces.wind;
+            forces.ambient_temp = self.forces.ambient_temp;
+            self.forces = forces;
+        }
+        self.space = space;
+        // The edits were made to the old space, so they can't be undone anymore
+        self.history.clear();
+    }
+
+    /// Undoes the last brush stroke, putting back the cells it changed
+    /// Returns false if there was nothing to undo
+    pub fn undo(&mut self) -> bool {
+        self.history.undo(&mut self.space)
+    }
+
+    /// Redoes the last brush stroke that was undone
+    /// Returns false if there was nothing to redo
+    pub fn redo(&mut self) -> bool {
+        self.history.redo(&mut self.space)
     }
 
     /// Toggles whether the simulation is running or paused
@@ -48,14 +207,108 @@ impl World {
     pub fn advance_simulation(&mut self) {
         // If the mouse is down, get its position and add cells
         if let Some((x, y)) = self.input.get_pos() {
-            // Add a small offset every other frame for a nicer drawing effect
-            let offset = if self.space.get_generation() % 2 == 0 { 0 } else { 1 };
-            // Add cells of the selected type at the mouse position
-            self.space.add(x + offset, y, self.input.get_selected_type());
+            if let Some(force) = self.input.get_selected_force() {
+                // Paint the selected force onto the area under the mouse instead of adding cells
+                self.forces.paint(x, y, force);
+            } else {
+                // Add a small offset every other frame for a nicer drawing effect
+                let offset = if self.space.get_generation() % 2 == 0 { 0 } else { 1 };
+                // Add cells of the selected type at the mouse position, and remember what was changed so it can be undone
+                let changed = self.space.add(x + offset, y, self.input.get_selected_type(), self.rng.as_mut());
+                self.history.record(self.input.get_stroke(), &self.space, changed);
+            }
+        } else {
+            // Once the mouse button is released, the stroke is finished and can be undone
+            self.history.finish();
         }
         
         // Run one tick of the simulation using the current simulator
-        self.simulator.tick(&mut self.space);
+        self.simulator.tick(&mut self.space, &self.forces, self.rng.as_mut());
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::CellType;
+
+    #[test]
+    fn runs_for_hundreds_of_ticks() {
+        // The generation counter wraps around every 256 ticks, so run for longer than that
+        let mut world = World::with_seed(20, 20, 1);
+        world.input.update_selected_type(CellType::Sand);
+        world.input.update_pos(10, 2);
+        world.input.update_down(true);
+        for _ in 0..300 {
+            world.advance_simulation();
+        }
+        world.input.update_down(false);
+        for _ in 0..300 {
+            world.advance_simulation();
+        }
+
+        let sand = (0..20).flat_map(|x| (0..20).map(move |y| (x, y)))
+            .filter(|&(x, y)| world.space.get_cell_type(x, y) == CellType::Sand)
+            .count();
+        assert!(sand > 0);
+    }
+
+    /// Paints a few materials into the world and runs it for the given number of ticks
+    fn run(world: &mut World, ticks: usize) {
+        for (x, cell_type) in [(5, CellType::Sand), (10, CellType::Water), (15, CellType::Wood), (15, CellType::Fire)] {
+            world.input.update_selected_type(cell_type);
+            world.input.update_pos(x, 4);
+            world.input.update_down(true);
+            for _ in 0..5 {
+                world.advance_simulation();
+            }
+            world.input.update_down(false);
+            world.advance_simulation();
+        }
+        for _ in 0..ticks {
+            world.advance_simulation();
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_result() {
+        // Each simulator makes different random decisions, so check both of them
+        for simulator in SIMULATORS.iter().map(|(name, _)| *name) {
+            let mut first = World::with_seed(20, 20, 42);
+            let mut second = World::with_seed(20, 20, 42);
+            assert!(first.select_simulator(simulator) && second.select_simulator(simulator));
+            run(&mut first, 200);
+            run(&mut second, 200);
+
+            // Every part of every cell has to be exactly the same (save() rounds the temperatures and leaves
+            // out the velocities and sparks, so comparing saves could miss a difference)
+            assert_eq!(first.space.get_generation(), second.space.get_generation());
+            for y in 0..20 {
+                for x in 0..20 {
+                    assert_eq!(first.space.get_cell(x, y), second.space.get_cell(x, y), "{} at ({}, {})", simulator, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn custom_rules_can_be_chosen_again() {
+        let mut world = World::with_seed(10, 10, 1);
+        assert!(!world.select_simulator(CUSTOM_RULES));
+
+        world.load_rules("rule\nsand  *  =>  empty  =\nempty *  =>  sand   =\n").unwrap();
+        assert_eq!(world.get_simulator_name(), CUSTOM_RULES);
+
+        // Switching to another simulator doesn't throw the rules away
+        assert!(world.select_simulator("Cellular"));
+        assert_eq!(world.get_simulator_name(), "Cellular");
+        assert!(world.select_simulator(CUSTOM_RULES));
+        assert_eq!(world.get_simulator_name(), CUSTOM_RULES);
+
+        // Rules with a mistake in them are rejected, and the last ones that worked are kept
+        assert!(world.load_rules("rule\ncheese  *  =>  =  =\n*  *  =>  =  =\n").is_err());
+        assert_eq!(world.get_simulator_name(), CUSTOM_RULES);
+        assert!(world.has_custom_rules());
+    }
+}
@@ -1,3 +1,116 @@
 import * as wasm from "fallingrust";
 
 wasm.run();
+
+// Load rules for the cellular simulator from a text file chosen by the user
+document.getElementById("rules-file").addEventListener("change", (event) => {
+  const file = event.target.files[0];
+  if (file) {
+    file.text().then((text) => {
+      try {
+        wasm.load_rules(text);
+      } catch (error) {
+        alert(`Couldn't load the rules from ${file.name}: ${error}`);
+      }
+    });
+  }
+});
+
+// Download the given data as a file with the given name
+function download(data, name, type) {
+  const link = document.createElement("a");
+  link.href = URL.createObjectURL(new Blob([data], { type }));
+  link.download = name;
+  link.click();
+  setTimeout(() => URL.revokeObjectURL(link.href), 0);
+}
+
+// Call the given function with the contents of the file chosen in the given file input, whenever a file is chosen
+function uploadFile(id, what, load) {
+  document.getElementById(id).addEventListener("change", (event) => {
+    const file = event.target.files[0];
+    if (file) {
+      file.arrayBuffer().then((buffer) => {
+        try {
+          load(new Uint8Array(buffer));
+        } catch (error) {
+          alert(`Couldn't load the ${what} from ${file.name}: ${error}`);
+        }
+      });
+    }
+  });
+}
+
+// Save all the cells in the world, and download them as a file
+document.getElementById("save-world").addEventListener("click", () => {
+  const data = wasm.save_world();
+  if (data) {
+    download(data, "fallingrust.sav", "application/octet-stream");
+  }
+});
+
+// Load the cells in the world from a file saved earlier
+uploadFile("world-file", "space", wasm.load_world);
+
+// Save the cells in the world as an image (one pixel for each cell), and download it
+document.getElementById("save-png").addEventListener("click", () => {
+  const data = wasm.save_png();
+  if (data) {
+    download(data, "fallingrust.png", "image/png");
+  }
+});
+
+// Load the cells in the world from an image, where each pixel becomes the material with the closest colour
+uploadFile("png-file", "image", wasm.load_png);
+
+// Fill in the list of slots that have a space saved in them
+function refreshSlots() {
+  const list = document.getElementById("slot-list");
+  list.replaceChildren(...wasm.list_slots().map((name) => new Option(name, name)));
+}
+refreshSlots();
+
+// Save the cells in the world into the browser's storage, in the slot named in the text box
+document.getElementById("save-slot").addEventListener("click", () => {
+  const name = document.getElementById("slot-name").value.trim();
+  if (!name) {
+    alert("Type a name for the slot first");
+    return;
+  }
+  try {
+    wasm.save_slot(name);
+  } catch (error) {
+    alert(`Couldn't save to the slot ${name}: ${error}`);
+  }
+  refreshSlots();
+  document.getElementById("slot-list").value = name;
+});
+
+// Load the cells in the world from the slot chosen in the list
+document.getElementById("load-slot").addEventListener("click", () => {
+  const name = document.getElementById("slot-list").value;
+  if (name) {
+    try {
+      wasm.load_slot(name);
+    } catch (error) {
+      alert(`Couldn't load the slot ${name}: ${error}`);
+    }
+  }
+});
+
+// Delete the slot chosen in the list
+document.getElementById("delete-slot").addEventListener("click", () => {
+  const name = document.getElementById("slot-list").value;
+  if (name && confirm(`Delete the slot ${name}?`)) {
+    wasm.delete_slot(name);
+    refreshSlots();
+  }
+});
+
+// Copy a link with all the cells in the world in it, so the space can be shared
+document.getElementById("share-link").addEventListener("click", () => {
+  const link = wasm.share_link();
+  if (link) {
+    navigator.clipboard.writeText(link).catch(() => prompt("Copy this link:", link));
+  }
+});